@@ -0,0 +1,309 @@
+//! Policies deciding when the bytes of a `Limiter` may be transferred. `TokenBucket`
+//! follows the `LimiterMode` of its options, other policies can be plugged in by
+//! implementing `RateAlgorithm`.
+use std::time::{Duration, Instant};
+
+use crate::window::WindowLog;
+use crate::{Clock, Constraint, LimiterMode, LimiterOptions};
+
+/// Policy limiting one direction of a `Limiter`, given to `Limiter::with_algorithm`
+/// as an instance carrying its own parameters and state.
+/// The instants come from the clock of the `Limiter`. After waiting for
+/// `time_until(nb)`, more bytes should be available than before.
+pub trait RateAlgorithm {
+    /// Create the algorithm applying `opts` from `now`, when options are given to a
+    /// direction that wasn't limited (with `Limiter::set_read_options` or a handle).
+    /// None by default, the direction then stays unlimited
+    fn from_options(_opts: LimiterOptions, _now: Instant) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Get the options applied by the algorithm
+    fn options(&self) -> &LimiterOptions;
+
+    /// Change the options applied from `now`
+    fn set_options(&mut self, opts: LimiterOptions, now: Instant);
+
+    /// Get the number of bytes that may be transferred at `now`
+    fn available(&self, now: Instant) -> u64;
+
+    /// Get the time to wait from `now` until `nb` bytes are allowed
+    fn time_until(&self, nb: u64, now: Instant) -> Duration;
+
+    /// Account for an operation allowed at `start`, that transferred `used` bytes once
+    /// done at `now`. Called with 0 bytes if the operation failed
+    fn consume(&mut self, used: u64, start: Instant, now: Instant);
+
+    /// Get the limit making an operation of `nb` bytes wait from `now`, reported to the
+    /// observer. The bytes by default
+    fn constraint(&self, _nb: u64, _now: Instant) -> Constraint {
+        Constraint::Bytes
+    }
+}
+
+/// Bucket of tokens following the `mode` of its options, drained at most at their
+/// peak rate if any, and by operations if the options cap them with `set_iops`.
+/// This is the algorithm of a `Limiter` by default, and it can limit
+/// any other resource (requests, messages, ...) one token per unit.
+/// It doesn't do any I/O nor read the time itself: the current instant is given to
+/// each call, only `acquire` sleeps on a clock.
+/// ```
+/// use stream_limiter::{LimiterOptions, TokenBucket};
+/// use std::time::{Duration, Instant};
+///
+/// // 10 requests per second, in bursts of at most 5
+/// let start = Instant::now();
+/// let mut bucket = TokenBucket::new(LimiterOptions::new(10, Duration::from_secs(1), 5), start);
+/// assert!(!bucket.try_acquire(1, start));
+/// let later = start + Duration::from_secs(1);
+/// assert!(bucket.try_acquire(5, later));
+/// assert_eq!(bucket.time_until(2, later), Duration::from_millis(200));
+/// ```
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    opts: LimiterOptions,
+    /// Instant of the last check, the instant at which the bucket was empty for the
+    /// GCRA and the leaky bucket
+    last_check: Instant,
+    /// Tokens left at the last check
+    additionnal_tokens: u64,
+    /// Log of the operations of the last window, for the sliding window mode
+    window: WindowLog,
+    /// Last check of the peak bucket, None while it's full
+    peak_check: Option<Instant>,
+    /// Tokens left in the peak bucket at its last check
+    peak_tokens: u64,
+    /// Bucket of operations, one token per acquire or operation
+    ops: Option<Box<TokenBucket>>,
+}
+
+impl TokenBucket {
+    /// Create an empty bucket filled as configured by the options from `now`
+    pub fn new(opts: LimiterOptions, now: Instant) -> TokenBucket {
+        TokenBucket {
+            last_check: now,
+            additionnal_tokens: 0,
+            window: WindowLog::default(),
+            peak_check: None,
+            peak_tokens: 0,
+            ops: opts
+                .iops
+                .as_ref()
+                .map(|iops| Box::new(TokenBucket::new((**iops).clone(), now))),
+            opts,
+        }
+    }
+
+    /// Get the options of the bucket
+    pub fn options(&self) -> &LimiterOptions {
+        &self.opts
+    }
+
+    /// Change the options of the bucket at `now`. The tokens gathered with the previous
+    /// options are kept, up to the new capacity and the new MTU
+    pub fn set_options(&mut self, opts: LimiterOptions, now: Instant) {
+        let tokens = self.tokens(now).min(opts.capacity());
+        (self.last_check, self.additionnal_tokens) = match opts.mode {
+            LimiterMode::TokenBucket => (now, tokens),
+            // The tokens only depend on the log of the window
+            LimiterMode::SlidingWindow => (now, 0),
+            // Put the instant at which the bucket was empty back by the tokens we have
+            LimiterMode::Gcra | LimiterMode::LeakyBucket { .. } => {
+                (now.checked_sub(opts.duration_for(tokens)).unwrap_or(now), 0)
+            }
+        };
+        // The peak bucket keeps its tokens up to the new MTU, it's only refilled when
+        // the peak rate is added or removed
+        (self.peak_check, self.peak_tokens) = match (self.opts.peak.as_ref(), opts.peak.as_ref()) {
+            (Some(_), Some(peak)) if self.peak_check.is_some() => {
+                (Some(now), self.peak_tokens(now).min(peak.bucket_size))
+            }
+            (Some(_), Some(_)) => (self.peak_check, self.peak_tokens),
+            _ => (None, 0),
+        };
+        self.ops = match (self.ops.take(), opts.iops.as_ref()) {
+            (Some(mut ops), Some(iops)) => {
+                ops.set_options((**iops).clone(), now);
+                Some(ops)
+            }
+            (None, Some(iops)) => Some(Box::new(TokenBucket::new((**iops).clone(), now))),
+            (_, None) => None,
+        };
+        self.opts = opts;
+    }
+
+    /// Get the most tokens that can be acquired at once
+    pub fn capacity(&self) -> u64 {
+        self.opts
+            .peak
+            .as_ref()
+            .map_or(self.opts.capacity(), |peak| {
+                self.opts.capacity().min(peak.bucket_size)
+            })
+    }
+
+    /// Get the number of tokens available at `now`, none while no operation is allowed
+    pub fn available(&self, now: Instant) -> u64 {
+        if self.ops.as_ref().is_some_and(|ops| ops.available(now) == 0) {
+            return 0;
+        }
+        self.tokens(now).min(self.peak_tokens(now))
+    }
+
+    /// Get the time to wait from `now` until `n` tokens are available.
+    /// `Duration::MAX` if `n` is higher than the capacity, as it would never be available
+    pub fn time_until(&self, n: u64, now: Instant) -> Duration {
+        if n > self.capacity() {
+            return Duration::MAX;
+        }
+        self.bytes_time_until(n, now).max(self.ops_time_until(now))
+    }
+
+    /// Get whether the tokens or the operations make `n` tokens wait from `now`
+    pub fn constraint(&self, n: u64, now: Instant) -> Constraint {
+        if self.ops_time_until(now) > self.bytes_time_until(n, now) {
+            Constraint::Operations
+        } else {
+            Constraint::Bytes
+        }
+    }
+
+    /// Take `n` tokens if they are available at `now`, returns false otherwise
+    pub fn try_acquire(&mut self, n: u64, now: Instant) -> bool {
+        if self.available(now) < n {
+            return false;
+        }
+        self.spend(n, now, now);
+        true
+    }
+
+    /// Take `n` tokens, sleeping on the clock until they are available.
+    /// Panics if `n` is higher than the capacity, as it would never be available
+    pub fn acquire(&mut self, n: u64, clock: &dyn Clock) {
+        assert!(
+            n <= self.capacity(),
+            "Capacity: {}, tokens acquired: {}",
+            self.capacity(),
+            n
+        );
+        loop {
+            let now = clock.now();
+            if self.try_acquire(n, now) {
+                return;
+            }
+            clock.sleep(self.time_until(n, now));
+        }
+    }
+
+    /// Give back at `now` `n` tokens that were acquired but not used, up to the capacity.
+    /// The operation is still counted by the IOPS limit
+    pub fn refund(&mut self, n: u64, now: Instant) {
+        match self.opts.mode {
+            LimiterMode::TokenBucket => {
+                self.additionnal_tokens = self.tokens(now).saturating_add(n);
+                self.last_check = now;
+            }
+            LimiterMode::SlidingWindow => self.window.refund(n),
+            // Put the instant at which the bucket was empty back, up to a full bucket
+            LimiterMode::Gcra | LimiterMode::LeakyBucket { .. } => {
+                let empty_at = self
+                    .last_check
+                    .checked_sub(self.opts.duration_for(n))
+                    .unwrap_or(self.last_check);
+                self.last_check =
+                    match now.checked_sub(self.opts.duration_for(self.opts.capacity())) {
+                        Some(full_since) => empty_at.max(full_since),
+                        None => empty_at,
+                    };
+            }
+        }
+        if self.peak_check.is_some() {
+            self.peak_tokens = self.peak_tokens.saturating_add(n);
+        }
+    }
+
+    /// Spend `used` tokens out of the ones available at `start`
+    fn spend(&mut self, used: u64, start: Instant, now: Instant) {
+        let tokens = self.tokens(start);
+        let peak_tokens = self.peak_tokens(start);
+        let (last_check, additionnal_tokens) =
+            self.opts.spend(Some(self.last_check), tokens, used, start);
+        self.last_check = last_check.unwrap_or(start);
+        self.additionnal_tokens = additionnal_tokens;
+        if self.opts.mode == LimiterMode::SlidingWindow {
+            // Log the operation once it's done, so that it leaves the window as late as
+            // the bytes it transferred
+            self.window.record(used, self.opts.window_time, now);
+        }
+        self.peak_check = Some(start);
+        self.peak_tokens = peak_tokens.saturating_sub(used);
+        if let Some(ops) = self.ops.as_mut() {
+            ops.spend(1, start, now);
+        }
+    }
+
+    /// Get the time to wait from `now` until `n` tokens are available, without the
+    /// operations
+    fn bytes_time_until(&self, n: u64, now: Instant) -> Duration {
+        self.opts.time_until(
+            Some(self.last_check),
+            &self.window,
+            self.tokens(now),
+            self.peak_tokens(now),
+            n,
+            now,
+        )
+    }
+
+    /// Get the time to wait from `now` until an operation is available
+    fn ops_time_until(&self, now: Instant) -> Duration {
+        self.ops
+            .as_ref()
+            .map_or(Duration::ZERO, |ops| ops.time_until(1, now))
+    }
+
+    /// Get the tokens of the bucket, without the peak rate
+    fn tokens(&self, now: Instant) -> u64 {
+        self.opts
+            .tokens(self.last_check, self.additionnal_tokens, &self.window, now)
+    }
+
+    /// Get the tokens of the peak bucket, u64::MAX without a peak rate
+    fn peak_tokens(&self, now: Instant) -> u64 {
+        self.opts
+            .peak_tokens(self.peak_check, self.peak_tokens, now)
+    }
+}
+
+impl RateAlgorithm for TokenBucket {
+    fn from_options(opts: LimiterOptions, now: Instant) -> Option<TokenBucket> {
+        Some(TokenBucket::new(opts, now))
+    }
+
+    fn options(&self) -> &LimiterOptions {
+        TokenBucket::options(self)
+    }
+
+    fn set_options(&mut self, opts: LimiterOptions, now: Instant) {
+        TokenBucket::set_options(self, opts, now);
+    }
+
+    fn available(&self, now: Instant) -> u64 {
+        TokenBucket::available(self, now)
+    }
+
+    fn time_until(&self, nb: u64, now: Instant) -> Duration {
+        TokenBucket::time_until(self, nb, now)
+    }
+
+    fn consume(&mut self, used: u64, start: Instant, now: Instant) {
+        self.spend(used, start, now);
+    }
+
+    fn constraint(&self, nb: u64, now: Instant) -> Constraint {
+        TokenBucket::constraint(self, nb, now)
+    }
+}
@@ -0,0 +1,144 @@
+//! Token bucket shared by the asynchronous limiters. Instead of sleeping, it registers
+//! a timer to wake up the task once there are enough tokens.
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::{LimiterOptions, RateAlgorithm, TokenBucket};
+
+/// Source of time and timers used by an asynchronous limiter to wait for its tokens
+pub trait Timer {
+    /// Future completing once the duration given to `sleep` has passed
+    type Sleep: Future<Output = ()>;
+
+    /// Get the current instant
+    fn now(&self) -> Instant;
+
+    /// Create a future completing after the given duration
+    fn sleep(&self, dur: Duration) -> Self::Sleep;
+}
+
+/// Token bucket of one direction of an asynchronous limiter
+pub(crate) struct AsyncBucket<T: Timer> {
+    /// None if the operations are performed on the raw stream
+    bucket: Option<TokenBucket>,
+    /// Instant at which the tokens were given to the pending operation
+    granted_at: Option<Instant>,
+    /// Instant at which the pending operation started waiting for tokens, to check its
+    /// timeout. Cleared as soon as the tokens are granted
+    op_start: Option<Instant>,
+    /// Timer to wake up the task once there are enough tokens, and the instant it goes off
+    sleep: Option<(Pin<Box<T::Sleep>>, Instant)>,
+}
+
+impl<T: Timer> AsyncBucket<T> {
+    pub(crate) fn new(timer: &T, opts: Option<LimiterOptions>) -> AsyncBucket<T> {
+        AsyncBucket {
+            bucket: opts.map(|opts| TokenBucket::new(opts, timer.now())),
+            granted_at: None,
+            op_start: None,
+            sleep: None,
+        }
+    }
+
+    /// Get if the operations are limited
+    pub(crate) fn is_limited(&self) -> bool {
+        self.bucket.is_some()
+    }
+
+    pub(crate) fn set_options(&mut self, timer: &T, opts: Option<LimiterOptions>) {
+        let now = timer.now();
+        self.bucket = match (self.bucket.take(), opts) {
+            (Some(mut bucket), Some(opts)) => {
+                bucket.set_options(opts, now);
+                Some(bucket)
+            }
+            (None, Some(opts)) => Some(TokenBucket::new(opts, now)),
+            (_, None) => None,
+        };
+        // Compute the sleep again with the new options
+        self.sleep = None;
+    }
+
+    /// Get the number of bytes we can transfer out of `len`, or register a timer
+    /// to wake up the task once it's worth doing the operation
+    pub(crate) fn poll_tokens(
+        &mut self,
+        timer: &T,
+        cx: &mut Context<'_>,
+        len: u64,
+        timeout_msg: &'static str,
+    ) -> Poll<io::Result<u64>> {
+        let Some(bucket) = self.bucket.as_ref() else {
+            return Poll::Ready(Ok(len));
+        };
+        let opts = bucket.options();
+        // Unless it was cut short by the timeout, a timer that went off before this poll
+        // without the tokens being there was set for another operation, whose future was
+        // dropped, or fell short of a rounding: the wait starts again
+        if let Some((_, wake_at)) = self.sleep.as_ref() {
+            let cut_by_timeout = self
+                .op_start
+                .zip(opts.timeout)
+                .and_then(|(start, t)| start.checked_add(t))
+                .is_some_and(|end| *wake_at >= end);
+            if *wake_at <= timer.now() && !cut_by_timeout {
+                self.op_start = None;
+                self.sleep = None;
+            }
+        }
+
+        loop {
+            // Check the tokens first, a timer or a start left by an operation whose future
+            // was dropped must not delay or fail the next one
+            let now = timer.now();
+            let nb_bytes = bucket.available(now).min(len);
+            // Get the number of bytes under which it's not worth doing the operation
+            let sleep_threshold = opts.sleep_threshold.min(len);
+            if nb_bytes >= sleep_threshold {
+                self.granted_at = Some(now);
+                self.op_start = None;
+                self.sleep = None;
+                return Poll::Ready(Ok(nb_bytes));
+            }
+
+            // Timeout if time since start of the wait is greater than timeout set in options
+            let start = *self.op_start.get_or_insert(now);
+            let elapsed = now.saturating_duration_since(start);
+            if let Some(t) = opts.timeout {
+                if elapsed >= t {
+                    self.op_start = None;
+                    self.sleep = None;
+                    return Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, timeout_msg)));
+                }
+            }
+
+            if let Some((sleep, _)) = self.sleep.as_mut() {
+                ready!(sleep.as_mut().poll(cx));
+                self.sleep = None;
+                continue;
+            }
+
+            // Compute the time required to get to the number of bytes required
+            let tsleep = bucket.time_until(sleep_threshold, now);
+            let tsleep_total = match opts.timeout {
+                Some(t) => tsleep.min(t.saturating_sub(elapsed)),
+                None => tsleep,
+            };
+            let wake_at = now.checked_add(tsleep_total).unwrap_or(now);
+            self.sleep = Some((Box::pin(timer.sleep(tsleep_total)), wake_at));
+        }
+    }
+
+    /// Spend `used` tokens out of the ones given by `poll_tokens`, once the operation is done
+    pub(crate) fn consume(&mut self, timer: &T, used: u64) {
+        let now = timer.now();
+        if let Some(bucket) = self.bucket.as_mut() {
+            let start = self.granted_at.unwrap_or(now);
+            bucket.consume(used, start, now);
+        }
+        self.granted_at = None;
+    }
+}
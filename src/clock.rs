@@ -0,0 +1,128 @@
+//! Time sources used by the `Limiter` to compute its tokens and to wait for them.
+//! The default `SystemClock` uses the wall clock, a `ManualClock` only moves when
+//! asked to, so throttled transfers can be checked without actually waiting.
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Source of time and way of waiting used by a `Limiter`
+pub trait Clock: Send + Sync {
+    /// Get the current instant
+    fn now(&self) -> Instant;
+
+    /// Block the current thread for the given duration
+    fn sleep(&self, dur: Duration);
+
+    /// Block the current thread for the given duration, or until `signal` is notified
+    /// after `generation`. Returns true if the wait was interrupted by the signal.
+    /// By default, only checks the signal before sleeping the whole duration with `sleep`.
+    fn wait(&self, signal: &Signal, generation: u64, dur: Duration) -> bool {
+        if signal.generation() != generation {
+            return true;
+        }
+        self.sleep(dur);
+        false
+    }
+}
+
+/// Wakes up the threads waiting on it, used to stop a sleep before its end
+/// (for example when tokens are given back to a shared bucket)
+#[derive(Debug, Default)]
+pub struct Signal {
+    generation: Mutex<u64>,
+    cond: Condvar,
+}
+
+impl Signal {
+    /// Create a new signal
+    pub fn new() -> Signal {
+        Signal::default()
+    }
+
+    /// Wake up all the threads waiting on this signal
+    pub fn notify(&self) {
+        let mut generation = self.generation.lock().expect("Signal lock poisoned");
+        *generation = generation.wrapping_add(1);
+        self.cond.notify_all();
+    }
+
+    /// Get the number of notifications so far, has to be read before checking
+    /// the condition we are about to wait for so no notification is missed.
+    pub fn generation(&self) -> u64 {
+        *self.generation.lock().expect("Signal lock poisoned")
+    }
+
+    /// Wait for a notification newer than `generation`, at most `dur` of wall clock time.
+    /// Returns true if the signal was notified.
+    pub fn wait_timeout(&self, generation: u64, dur: Duration) -> bool {
+        let guard = self.generation.lock().expect("Signal lock poisoned");
+        let (guard, _) = self
+            .cond
+            .wait_timeout_while(guard, dur, |g| *g == generation)
+            .expect("Signal lock poisoned");
+        *guard != generation
+    }
+}
+
+/// Wall clock, uses `Instant::now` and `std::thread::sleep`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, dur: Duration) {
+        std::thread::sleep(dur);
+    }
+
+    fn wait(&self, signal: &Signal, generation: u64, dur: Duration) -> bool {
+        signal.wait_timeout(generation, dur)
+    }
+}
+
+/// Virtual clock, time only goes forward with `advance` or when something sleeps on it.
+/// Sleeping on this clock returns immediately after moving the time forward.
+#[derive(Debug)]
+pub struct ManualClock {
+    start: Instant,
+    now: Mutex<Instant>,
+}
+
+impl ManualClock {
+    /// Create a new virtual clock, starting at the current instant
+    pub fn new() -> ManualClock {
+        let start = Instant::now();
+        ManualClock {
+            start,
+            now: Mutex::new(start),
+        }
+    }
+
+    /// Move the time of the clock forward
+    pub fn advance(&self, dur: Duration) {
+        let mut now = self.now.lock().expect("Manual clock lock poisoned");
+        *now += dur;
+    }
+
+    /// Get the virtual time elapsed since the creation of the clock
+    pub fn elapsed(&self) -> Duration {
+        self.now().saturating_duration_since(self.start)
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().expect("Manual clock lock poisoned")
+    }
+
+    fn sleep(&self, dur: Duration) {
+        self.advance(dur);
+    }
+}
@@ -0,0 +1,146 @@
+//! Asynchronous limiter for the `futures::io` traits, enabled with the `futures-io` feature.
+//! It doesn't depend on any runtime, the timer used to wait for the tokens is given
+//! by the user so the same limited stream works on any executor.
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_io::{AsyncRead, AsyncWrite};
+
+use crate::async_bucket::{AsyncBucket, Timer};
+use crate::LimiterOptions;
+
+/// Asynchronous version of the `Limiter`, for streams implementing `futures_io::AsyncRead`
+/// and `futures_io::AsyncWrite`, waiting for its tokens with the given `Timer`.
+/// Unlike `Limiter::read`, a read returns as soon as some bytes were read, as any
+/// `AsyncRead`.
+pub struct FuturesLimiter<S, T: Timer> {
+    pub stream: S,
+    timer: T,
+    read: AsyncBucket<T>,
+    write: AsyncBucket<T>,
+}
+
+impl<S, T: Timer> FuturesLimiter<S, T> {
+    /// Create a new `FuturesLimiter` with the given options passed in parameter
+    /// If an option is None, the operation will be performed on the raw stream
+    pub fn new(
+        stream: S,
+        read_opt: Option<LimiterOptions>,
+        write_opt: Option<LimiterOptions>,
+        timer: T,
+    ) -> FuturesLimiter<S, T> {
+        FuturesLimiter {
+            stream,
+            read: AsyncBucket::new(&timer, read_opt),
+            write: AsyncBucket::new(&timer, write_opt),
+            timer,
+        }
+    }
+
+    /// Change the options limiting the read operations, without resetting the bucket.
+    /// The tokens gathered with the previous options are kept (up to the new bucket size)
+    pub fn set_read_options(&mut self, read_opt: Option<LimiterOptions>) {
+        self.read.set_options(&self.timer, read_opt);
+    }
+
+    /// Change the options limiting the write operations, without resetting the bucket.
+    /// The tokens gathered with the previous options are kept (up to the new bucket size)
+    pub fn set_write_options(&mut self, write_opt: Option<LimiterOptions>) {
+        self.write.set_options(&self.timer, write_opt);
+    }
+
+    /// Get if this FuturesLimiter limits the read or write stream (or none)
+    pub fn limits(&self) -> (bool, bool) {
+        (self.read.is_limited(), self.write.is_limited())
+    }
+
+    /// Get the timer used by this FuturesLimiter
+    pub fn timer(&self) -> &T {
+        &self.timer
+    }
+
+    /// Get the raw stream, deconstruct the FuturesLimiter struct.
+    pub fn get_stream(self) -> S {
+        self.stream
+    }
+}
+
+impl<S, T> AsyncRead for FuturesLimiter<S, T>
+where
+    S: AsyncRead + Unpin,
+    T: Timer + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let len = u64::try_from(buf.len()).expect("R buflen to u64");
+        // If the stream isn't limited, read instantly instead
+        if !this.read.is_limited() || len == 0 {
+            return Pin::new(&mut this.stream).poll_read(cx, buf);
+        }
+
+        let nb_bytes_readable =
+            ready!(this.read.poll_tokens(&this.timer, cx, len, "Read timeout"))?;
+        let limit = usize::try_from(nb_bytes_readable).expect("R limit to usize");
+        match Pin::new(&mut this.stream).poll_read(cx, &mut buf[..limit]) {
+            Poll::Ready(Ok(read_now)) => {
+                this.read.consume(
+                    &this.timer,
+                    u64::try_from(read_now).expect("R read_now to u64"),
+                );
+                Poll::Ready(Ok(read_now))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S, T> AsyncWrite for FuturesLimiter<S, T>
+where
+    S: AsyncWrite + Unpin,
+    T: Timer + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let len = u64::try_from(buf.len()).expect("W buflen to u64");
+        // If the stream isn't limited, write instantly instead
+        if !this.write.is_limited() || len == 0 {
+            return Pin::new(&mut this.stream).poll_write(cx, buf);
+        }
+
+        let nb_bytes_writable =
+            ready!(this
+                .write
+                .poll_tokens(&this.timer, cx, len, "Write timeout"))?;
+        let limit = usize::try_from(nb_bytes_writable).expect("W limit to usize");
+        match Pin::new(&mut this.stream).poll_write(cx, &buf[..limit]) {
+            Poll::Ready(Ok(write_now)) => {
+                this.write.consume(
+                    &this.timer,
+                    u64::try_from(write_now).expect("W write_now to u64"),
+                );
+                Poll::Ready(Ok(write_now))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Flush the underlying stream
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_close(cx)
+    }
+}
@@ -0,0 +1,301 @@
+//! Remote control of a `Limiter` owned by another thread.
+//! Changes made through a `LimiterHandle` wake up the owning thread if it is
+//! sleeping inside a read or a write, so they take effect immediately.
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, Signal};
+use crate::meter::{Rates, ThroughputMeter};
+use crate::stats::AtomicStats;
+use crate::{Direction, LimiterOptions, LimiterStats};
+
+/// Changes requested through a handle, not yet applied by the limiter
+#[derive(Default)]
+pub(crate) struct Pending {
+    pub(crate) read_opt: Option<Option<LimiterOptions>>,
+    pub(crate) write_opt: Option<Option<LimiterOptions>>,
+}
+
+/// State shared between a `Limiter` and its handles
+#[derive(Default)]
+pub(crate) struct Control {
+    pending: Mutex<Pending>,
+    /// Set when there are pending changes, checked by the limiter on each loop
+    changed: AtomicBool,
+    paused: (AtomicBool, AtomicBool),
+    stats: (AtomicStats, AtomicStats),
+    meters: Mutex<(ThroughputMeter, ThroughputMeter)>,
+    /// Notified on each change, the limiter sleeps on it
+    signal: Signal,
+    /// Signal of the shared bucket the limiter is waiting on, if any
+    waiting_on: Mutex<Option<Arc<Signal>>>,
+}
+
+impl Control {
+    /// Get the generation of the signal, has to be read before computing the tokens
+    pub(crate) fn generation(&self) -> u64 {
+        self.signal.generation()
+    }
+
+    /// Take the changes requested through the handles, if any
+    pub(crate) fn take_pending(&self) -> Option<Pending> {
+        if !self.changed.swap(false, Ordering::AcqRel) {
+            return None;
+        }
+        let mut pending = self.pending.lock().expect("Control lock poisoned");
+        Some(std::mem::take(&mut *pending))
+    }
+
+    pub(crate) fn is_paused(&self, dir: Direction) -> bool {
+        match dir {
+            Direction::Read => self.paused.0.load(Ordering::Acquire),
+            Direction::Write => self.paused.1.load(Ordering::Acquire),
+        }
+    }
+
+    /// Get the statistics of a direction
+    pub(crate) fn stats(&self, dir: Direction) -> &AtomicStats {
+        match dir {
+            Direction::Read => &self.stats.0,
+            Direction::Write => &self.stats.1,
+        }
+    }
+
+    /// Count an operation on the inner stream, in the statistics and the throughput
+    pub(crate) fn add_io(&self, dir: Direction, nb: u64, io_time: Duration, now: Instant) {
+        self.stats(dir).add_io(nb, io_time);
+        if nb > 0 {
+            let mut meters = self.meters.lock().expect("Control lock poisoned");
+            match dir {
+                Direction::Read => meters.0.record(nb, now),
+                Direction::Write => meters.1.record(nb, now),
+            }
+        }
+    }
+
+    /// Get the average rates of the read and write operations at `now`
+    pub(crate) fn throughput(&self, now: Instant) -> (Rates, Rates) {
+        let meters = self.meters.lock().expect("Control lock poisoned");
+        (meters.0.rates(now), meters.1.rates(now))
+    }
+
+    /// Replace the throughput meters, the averages start again from 0
+    pub(crate) fn set_horizons(&self, horizons: &[Duration]) {
+        let meter = ThroughputMeter::new(horizons);
+        *self.meters.lock().expect("Control lock poisoned") = (meter.clone(), meter);
+    }
+
+    /// Sleep until the given duration passed, or a change is made through a handle.
+    /// Returns true if the sleep was interrupted.
+    pub(crate) fn sleep(
+        &self,
+        dir: Direction,
+        clock: &dyn Clock,
+        generation: u64,
+        dur: Duration,
+    ) -> bool {
+        let start = clock.now();
+        let interrupted = clock.wait(&self.signal, generation, dur);
+        self.stats(dir)
+            .add_sleep(clock.now().saturating_duration_since(start));
+        interrupted
+    }
+
+    /// Wait on the signal of a shared bucket, a change made through a handle
+    /// also interrupts the wait.
+    pub(crate) fn wait_shared(
+        &self,
+        dir: Direction,
+        clock: &dyn Clock,
+        bucket_signal: &Arc<Signal>,
+        bucket_generation: u64,
+        generation: u64,
+        dur: Duration,
+    ) {
+        *self.waiting_on.lock().expect("Control lock poisoned") = Some(bucket_signal.clone());
+        // A change made before we registered the bucket signal wouldn't wake us up
+        if self.signal.generation() == generation {
+            let start = clock.now();
+            clock.wait(bucket_signal, bucket_generation, dur);
+            self.stats(dir)
+                .add_sleep(clock.now().saturating_duration_since(start));
+        }
+        *self.waiting_on.lock().expect("Control lock poisoned") = None;
+    }
+
+    /// Wake up the limiter, wherever it is sleeping
+    pub(crate) fn notify(&self) {
+        self.signal.notify();
+        if let Some(signal) = self
+            .waiting_on
+            .lock()
+            .expect("Control lock poisoned")
+            .as_ref()
+        {
+            signal.notify();
+        }
+    }
+}
+
+/// A handle to control a `Limiter` from another thread, get one with `Limiter::handle`.
+/// Changes take effect even if the limiter is sleeping inside a read or a write.
+#[derive(Clone)]
+pub struct LimiterHandle {
+    pub(crate) control: Arc<Control>,
+    pub(crate) clock: Arc<dyn Clock>,
+}
+
+impl LimiterHandle {
+    /// Change the options limiting the read operations, as `Limiter::set_read_options`
+    pub fn set_read_options(&self, read_opt: Option<LimiterOptions>) {
+        self.control
+            .pending
+            .lock()
+            .expect("Control lock poisoned")
+            .read_opt = Some(read_opt);
+        self.control.changed.store(true, Ordering::Release);
+        self.control.notify();
+    }
+
+    /// Change the options limiting the write operations, as `Limiter::set_write_options`
+    pub fn set_write_options(&self, write_opt: Option<LimiterOptions>) {
+        self.control
+            .pending
+            .lock()
+            .expect("Control lock poisoned")
+            .write_opt = Some(write_opt);
+        self.control.changed.store(true, Ordering::Release);
+        self.control.notify();
+    }
+
+    /// Pause the read operations, a read in progress waits until they are resumed
+    /// (or until its timeout)
+    pub fn pause_read(&self) {
+        self.control.paused.0.store(true, Ordering::Release);
+        self.control.notify();
+    }
+
+    /// Resume the read operations
+    pub fn resume_read(&self) {
+        self.control.paused.0.store(false, Ordering::Release);
+        self.control.notify();
+    }
+
+    /// Pause the write operations, a write in progress waits until they are resumed
+    /// (or until its timeout)
+    pub fn pause_write(&self) {
+        self.control.paused.1.store(true, Ordering::Release);
+        self.control.notify();
+    }
+
+    /// Resume the write operations
+    pub fn resume_write(&self) {
+        self.control.paused.1.store(false, Ordering::Release);
+        self.control.notify();
+    }
+
+    /// Get if the read and write operations are paused
+    pub fn paused(&self) -> (bool, bool) {
+        (
+            self.control.is_paused(Direction::Read),
+            self.control.is_paused(Direction::Write),
+        )
+    }
+
+    /// Get the number of bytes read through the limiter
+    pub fn bytes_read(&self) -> u64 {
+        self.control.stats.0.bytes()
+    }
+
+    /// Get the number of bytes written through the limiter
+    pub fn bytes_written(&self) -> u64 {
+        self.control.stats.1.bytes()
+    }
+
+    /// Get the statistics of the read and write operations of the limiter
+    pub fn stats(&self) -> (LimiterStats, LimiterStats) {
+        (
+            self.control.stats.0.snapshot(),
+            self.control.stats.1.snapshot(),
+        )
+    }
+
+    /// Get the average rates of the read and write operations, as `Limiter::throughput`
+    pub fn throughput(&self) -> (Rates, Rates) {
+        self.control.throughput(self.clock.now())
+    }
+}
+
+/// Interrupts the throttling of every `Limiter` it's given to, for example on shutdown.
+/// Once cancelled, a read or write returns the bytes transferred so far, or an error
+/// of kind `io::ErrorKind::ConnectionAborted` if there are none, so that
+/// `Read::read_exact` and `Write::write_all` stop as well. The error holds a `Cancelled`
+/// to tell it apart from a connection aborted by the peer.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<CancelInner>,
+}
+
+/// Inner error of the reads and writes stopped by a `CancelToken`, checked with
+/// `err.get_ref().is_some_and(|inner| inner.is::<Cancelled>())`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled(pub Direction);
+
+impl Cancelled {
+    /// Get the error returned by a cancelled operation in the direction
+    pub(crate) fn error(dir: Direction) -> io::Error {
+        io::Error::new(io::ErrorKind::ConnectionAborted, Cancelled(dir))
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Direction::Read => write!(f, "Read cancelled"),
+            Direction::Write => write!(f, "Write cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+#[derive(Default)]
+struct CancelInner {
+    cancelled: AtomicBool,
+    /// Limiters to wake up on cancellation
+    controls: Mutex<Vec<Weak<Control>>>,
+}
+
+impl CancelToken {
+    /// Create a new token, not cancelled
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Cancel the operations of every limiter using this token, wakes them up
+    /// if they are sleeping
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Release);
+        let controls =
+            std::mem::take(&mut *self.inner.controls.lock().expect("Cancel lock poisoned"));
+        for control in controls.iter().filter_map(Weak::upgrade) {
+            control.notify();
+        }
+    }
+
+    /// Get if the token was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Wake up this limiter when the token is cancelled
+    pub(crate) fn register(&self, control: &Arc<Control>) {
+        let mut controls = self.inner.controls.lock().expect("Cancel lock poisoned");
+        // Forget the limiters dropped meanwhile
+        controls.retain(|control| control.strong_count() > 0);
+        controls.push(Arc::downgrade(control));
+    }
+}
@@ -14,12 +14,182 @@
 //! limiter.read(&mut buf).unwrap();
 //! assert_eq!(now.elapsed().as_secs(), 10);
 //! ```
+//!
+//! The time can be virtual by giving a `ManualClock` to the `Limiter`,
+//! the same read is then performed without waiting:
+//! ```
+//! use stream_limiter::{Limiter, LimiterOptions, ManualClock};
+//! use std::time::Duration;
+//! use std::io::prelude::*;
+//! use std::fs::File;
+//! use std::sync::Arc;
+//!
+//! let clock = Arc::new(ManualClock::new());
+//! let mut file = File::open("test_resources/test.txt").unwrap();
+//! let opts = LimiterOptions::new(1, Duration::from_secs(1), 1);
+//! let mut limiter = Limiter::with_clock(file, Some(opts), None, clock.clone());
+//! let mut buf = [0u8; 10];
+//! limiter.read(&mut buf).unwrap();
+//! assert_eq!(clock.elapsed(), Duration::from_secs(10));
+//! ```
+//!
+//! With the `tokio` feature, a `TokioLimiter` applies the same `LimiterOptions`
+//! to streams implementing tokio's `AsyncRead` and `AsyncWrite`.
+//! With the `futures-io` feature, a `FuturesLimiter` does the same for the
+//! `futures::io` traits, on any executor given a `Timer`.
+//! With the `mio` feature, a `Limiter` wrapping a mio stream can be registered in a
+//! `mio::Poll`, see `poll_timeout`, and a `LimiterScheduler` drives many of them
+//! from a single thread.
+//! With the `tracing` feature, the reads and writes emit spans and events with their
+//! direction and the name given by `Limiter::set_name`.
+//! With the `metrics` feature, the bytes, sleeps, timeouts and errors of every limiter
+//! are published to the `metrics` facade, labelled by name and direction.
+//!
+//! The operations are limited by a `TokenBucket` following the `LimiterMode` of the
+//! options. Another policy can be given to `Limiter::with_algorithm` by implementing
+//! `RateAlgorithm`. A `TokenBucket` can also be used on its own, without any I/O, to
+//! limit other resources with `try_acquire`, `acquire` and `refund`.
+//! `LimiterOptions::set_iops` also caps the operations on the inner stream, and the
+//! `LimiterObserver` is told whether the bytes or the operations made them wait.
 use std::debug_assert;
 use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::sync::Arc;
+#[cfg(feature = "metrics")]
+use std::sync::OnceLock;
 use std::time::{Duration, Instant};
 
+mod algorithm;
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+mod async_bucket;
+mod clock;
+#[cfg(feature = "futures-io")]
+mod futures_io;
+mod handle;
+mod meter;
+#[cfg(feature = "metrics")]
+mod metrics_export;
+#[cfg(feature = "mio")]
+mod mio_source;
+mod observer;
+#[cfg(feature = "mio")]
+mod scheduler;
+mod shared;
+mod stats;
 #[cfg(test)]
 mod tests;
+#[cfg(feature = "tokio")]
+mod tokio_io;
+mod window;
+
+pub use algorithm::{RateAlgorithm, TokenBucket};
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+pub use async_bucket::Timer;
+pub use clock::{Clock, ManualClock, Signal, SystemClock};
+#[cfg(feature = "futures-io")]
+pub use futures_io::FuturesLimiter;
+use handle::Control;
+pub use handle::{CancelToken, Cancelled, LimiterHandle};
+pub use meter::{Rates, ThroughputMeter, DEFAULT_HORIZONS};
+#[cfg(feature = "metrics")]
+use metrics_export::LimiterMetrics;
+#[cfg(feature = "mio")]
+pub use mio_source::poll_timeout;
+pub use observer::LimiterObserver;
+#[cfg(feature = "mio")]
+pub use scheduler::{LimiterScheduler, SchedulerEvent, StreamId};
+pub use shared::SharedBucket;
+use shared::SharedLink;
+pub use stats::LimiterStats;
+#[cfg(feature = "tokio")]
+pub use tokio_io::{TokioLimiter, TokioTimer};
+use window::WindowLog;
+
+/// Longest sleep while the operations are paused without any timeout,
+/// the pause is checked again after it
+const PAUSE_CHECK: Duration = Duration::from_secs(1);
+
+/// Direction of an I/O operation through a `Limiter`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Read,
+    Write,
+}
+
+impl Direction {
+    /// Get the direction as "read" or "write", as used in the diagnostics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Read => "read",
+            Direction::Write => "write",
+        }
+    }
+
+    /// Get an error of an operation in this direction, as "Read <what>" or "Write <what>"
+    fn error(&self, kind: io::ErrorKind, what: &str) -> io::Error {
+        let dir = match self {
+            Direction::Read => "Read",
+            Direction::Write => "Write",
+        };
+        io::Error::new(kind, format!("{dir} {what}"))
+    }
+}
+
+/// Get the part of a pair of read and write values for the given direction
+fn by_dir<T>(pair: &(T, T), dir: Direction) -> &T {
+    match dir {
+        Direction::Read => &pair.0,
+        Direction::Write => &pair.1,
+    }
+}
+
+/// Get the part of a pair of read and write values for the given direction
+fn by_dir_mut<T>(pair: &mut (T, T), dir: Direction) -> &mut T {
+    match dir {
+        Direction::Read => &mut pair.0,
+        Direction::Write => &mut pair.1,
+    }
+}
+
+/// Algorithm deciding when the tokens are available, set with `LimiterOptions::set_mode`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LimiterMode {
+    /// Tokens are added to a bucket of `bucket_size` as time passes, and spent by the
+    /// operations
+    #[default]
+    TokenBucket,
+    /// Generic cell rate algorithm: only the instant at which the bucket was empty is
+    /// kept, and pushed back by the exact time each byte takes. It doesn't lose the
+    /// fractions of tokens, and gives the exact time to wait for the next operation.
+    Gcra,
+    /// Leaky bucket shaping the traffic at a constant rate: the operations transfer at
+    /// most `chunk_size` bytes, evenly spaced by the time it takes to generate them.
+    /// At most one chunk is gathered while idle, so there is never any burst.
+    LeakyBucket { chunk_size: u64 },
+    /// Strict sliding window: over any interval of `window_time`, at most
+    /// `window_length` bytes are transferred. A compact log of the operations of the
+    /// last window is kept, and the operations wait for the oldest ones to leave it.
+    SlidingWindow,
+}
+
+/// Limit of the options making an operation wait, reported by the `LimiterObserver`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Constraint {
+    /// The bytes per window (or the peak rate)
+    Bytes,
+    /// The operations per window, set with `LimiterOptions::set_iops`
+    Operations,
+}
+
+impl Constraint {
+    /// Get the name of the constraint, as used in the diagnostics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Constraint::Bytes => "bytes",
+            Constraint::Operations => "operations",
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct LimiterOptions {
@@ -41,6 +211,14 @@ pub struct LimiterOptions {
     pub stream_cap_limit: u64,
     /// Value under which we have to sleep to get more tokens
     pub sleep_threshold: u64,
+    /// Minimal size of the operations, set with `set_min_operation_size`
+    pub min_operation_size: Option<u64>,
+    /// Second bucket limiting how fast the tokens are spent, set with `set_peak_rate`
+    pub peak: Option<Box<LimiterOptions>>,
+    /// Bucket of operations on the inner stream (IOPS), set with `set_iops`
+    pub iops: Option<Box<LimiterOptions>>,
+    /// Algorithm limiting the rate
+    pub mode: LimiterMode,
 }
 
 impl LimiterOptions {
@@ -79,6 +257,10 @@ impl LimiterOptions {
             bucket_size,
             tsleep,
             timeout: None,
+            min_operation_size: None,
+            peak: None,
+            iops: None,
+            mode: LimiterMode::TokenBucket,
         }
     }
 }
@@ -88,6 +270,8 @@ impl LimiterOptions {
     /// The number of tokens available in order to read / write will have to be at
     /// least this size (expect there is not enough data left)
     /// Useful for TcpStream operations (Tcp operation has 60Kb of data / packet)
+    /// It's capped by the peak MTU and by the capacity of the mode, whatever the order
+    /// in which they are set.
     pub fn set_min_operation_size(&mut self, val: u64) {
         assert_ne!(val, 0);
         assert!(
@@ -96,7 +280,72 @@ impl LimiterOptions {
             self.bucket_size,
             val
         );
-        self.sleep_threshold = self.sleep_threshold.max(val);
+        self.min_operation_size = Some(val);
+        self.update_limits();
+    }
+
+    /// Sets a peak rate of `peak_length` bytes every `peak_time`, as the `peakrate` and
+    /// `mtu` of tc-tbf. The tokens of the bucket are then spent at most at this rate,
+    /// by operations of at most `mtu` bytes, instead of all at once after an idle period.
+    /// The long-term average still follows `window_length / window_time`, so the peak
+    /// rate should be higher. The minimal operation size is capped by `mtu`.
+    /// Only applied by the `TokenBucket`, a `SharedBucket` doesn't support it.
+    pub fn set_peak_rate(&mut self, peak_length: u64, peak_time: Duration, mtu: u64) {
+        assert_ne!(mtu, 0);
+        self.peak = Some(Box::new(LimiterOptions::new(peak_length, peak_time, mtu)));
+        self.update_limits();
+    }
+
+    /// Caps the operations on the inner stream at `ops_length` every `ops_time`, on
+    /// top of the bytes. At most `burst` operations are gathered while idle.
+    /// Each read or write on the inner stream takes one operation, whatever its size,
+    /// so small operations are limited by the IOPS and large ones by the bytes.
+    /// Only applied by the `TokenBucket`, a `SharedBucket` doesn't support it.
+    pub fn set_iops(&mut self, ops_length: u64, ops_time: Duration, burst: u64) {
+        assert_ne!(burst, 0);
+        self.iops = Some(Box::new(LimiterOptions::new(ops_length, ops_time, burst)));
+    }
+
+    /// Sets the algorithm limiting the rate, a token bucket by default.
+    /// A leaky bucket overrides the minimal operation size with its chunk size (still
+    /// capped by the peak MTU), and a sliding window caps it with the window length.
+    /// The previous one applies again when switching to another mode.
+    /// A `SharedBucket` only supports the token bucket and the GCRA, which behave the same.
+    pub fn set_mode(&mut self, mode: LimiterMode) {
+        if let LimiterMode::LeakyBucket { chunk_size } = mode {
+            assert_ne!(chunk_size, 0);
+        }
+        self.mode = mode;
+        self.update_limits();
+    }
+
+    /// Compute the size limits of the operations from the window, the minimal operation
+    /// size, the peak rate and the mode
+    fn update_limits(&mut self) {
+        let mut stream_cap_limit = std::cmp::min(self.window_length, self.bucket_size);
+        let mut sleep_threshold = stream_cap_limit.max(self.min_operation_size.unwrap_or_default());
+        if let LimiterMode::LeakyBucket { chunk_size } = self.mode {
+            stream_cap_limit = chunk_size;
+            sleep_threshold = chunk_size;
+        }
+        // The bucket never gathers more tokens than its capacity, nor the peak bucket
+        // more than its MTU, waiting for more would last forever
+        sleep_threshold = sleep_threshold.min(self.capacity());
+        if let Some(peak) = self.peak.as_ref() {
+            stream_cap_limit = stream_cap_limit.min(peak.bucket_size);
+            sleep_threshold = sleep_threshold.min(peak.bucket_size);
+        }
+        self.stream_cap_limit = stream_cap_limit;
+        self.sleep_threshold = sleep_threshold;
+    }
+
+    /// Get the maximum number of tokens gathered by the bucket
+    pub(crate) fn capacity(&self) -> u64 {
+        match self.mode {
+            LimiterMode::LeakyBucket { chunk_size } => chunk_size,
+            LimiterMode::SlidingWindow => self.bucket_size.min(self.window_length),
+            _ => self.bucket_size,
+        }
     }
 
     /// Sets a timeout so we can interrupt a limited stream read / write once it has
@@ -104,23 +353,160 @@ impl LimiterOptions {
     pub fn set_timeout(&mut self, timeout: Duration) {
         self.timeout = Some(timeout);
     }
+
+    /// Get the number of tokens generated since the last check, capped by the bucket size
+    pub(crate) fn tokens_since(&self, last_check: Instant, now: Instant) -> u64 {
+        // Get the number of nanoseconds since last check
+        // Will cap the last check at a duration of about 584 years
+        let elapsed =
+            u64::try_from(now.saturating_duration_since(last_check).as_nanos()).unwrap_or(u64::MAX);
+        // Cross product to get the number of bytes we can read / write
+        match elapsed
+            .saturating_mul(self.window_length)
+            .checked_div(self.wtime_ns)
+        {
+            Some(tokens) => std::cmp::min(tokens, self.capacity()),
+            // If we don't wait at all because of options, we can use u64::MAX bytes at once
+            None => u64::MAX,
+        }
+    }
+
+    /// Get the number of tokens available at `now`, from the tokens generated since the
+    /// last check and the additionnal ones, or from the log of a sliding window
+    pub(crate) fn tokens(
+        &self,
+        last_check: Instant,
+        additionnal_tokens: u64,
+        window: &WindowLog,
+        now: Instant,
+    ) -> u64 {
+        match self.mode {
+            LimiterMode::SlidingWindow => window
+                .available(self.window_length, self.window_time, now)
+                .min(self.capacity()),
+            _ => self
+                .tokens_since(last_check, now)
+                .saturating_add(additionnal_tokens)
+                .min(self.capacity()),
+        }
+    }
+
+    /// Get the number of tokens in the peak bucket, u64::MAX without a peak rate.
+    /// The peak bucket is full until it's used (`last_check` is None)
+    pub(crate) fn peak_tokens(
+        &self,
+        last_check: Option<Instant>,
+        additionnal_tokens: u64,
+        now: Instant,
+    ) -> u64 {
+        match (self.peak.as_ref(), last_check) {
+            (None, _) => u64::MAX,
+            (Some(peak), None) => peak.bucket_size,
+            (Some(peak), Some(last_check)) => peak
+                .tokens_since(last_check, now)
+                .saturating_add(additionnal_tokens)
+                .min(peak.bucket_size),
+        }
+    }
+
+    /// Get the exact time it takes to generate `nb` tokens, rounded up to the nanosecond
+    pub(crate) fn duration_for(&self, nb: u64) -> Duration {
+        let nanos = (u128::from(nb) * u128::from(self.wtime_ns))
+            .div_ceil(u128::from(self.window_length).max(1));
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+
+    /// Get the last check and additionnal tokens once `used` tokens are spent out of
+    /// the `tokens` available at `now`
+    pub(crate) fn spend(
+        &self,
+        last_check: Option<Instant>,
+        tokens: u64,
+        used: u64,
+        now: Instant,
+    ) -> (Option<Instant>, u64) {
+        match self.mode {
+            LimiterMode::TokenBucket => (Some(now), tokens.saturating_sub(used)),
+            // The operations are logged in the window by the caller
+            LimiterMode::SlidingWindow => (Some(now), 0),
+            // The last check is the instant at which the bucket was empty, it can't be
+            // older than the time to fill the bucket
+            LimiterMode::Gcra | LimiterMode::LeakyBucket { .. } => {
+                let empty_at = last_check.unwrap_or(now);
+                let empty_at = match now.checked_sub(self.duration_for(self.capacity())) {
+                    Some(full_since) => empty_at.max(full_since),
+                    None => empty_at,
+                };
+                (Some(empty_at + self.duration_for(used)), 0)
+            }
+        }
+    }
+
+    /// Get the time to wait from `now` until both the bucket and the peak bucket have
+    /// `nb` tokens, given the tokens they have now
+    pub(crate) fn time_until(
+        &self,
+        last_check: Option<Instant>,
+        window: &WindowLog,
+        tokens: u64,
+        peak_tokens: u64,
+        nb: u64,
+        now: Instant,
+    ) -> Duration {
+        let tsleep = match (self.mode, last_check) {
+            (LimiterMode::Gcra | LimiterMode::LeakyBucket { .. }, Some(empty_at)) => {
+                (empty_at + self.duration_for(nb)).saturating_duration_since(now)
+            }
+            (LimiterMode::SlidingWindow, _) => {
+                window.time_until(nb, self.window_length, self.window_time, now)
+            }
+            // Rounded up, the truncated `tsleep` would wake up before the last token
+            _ => self.duration_for(nb.saturating_sub(tokens)),
+        };
+        match self.peak.as_ref() {
+            Some(peak) => tsleep.max(peak.duration_for(nb.saturating_sub(peak_tokens))),
+            None => tsleep,
+        }
+    }
 }
 
 /// A `Limiter` is a wrapper around a stream that implement `Read` and `Write`
 /// that limits the rate at which it can be read or written.
-pub struct Limiter<S>
+/// The algorithm limiting the operations is a `TokenBucket` unless given with
+/// `Limiter::with_algorithm`.
+pub struct Limiter<S, A = TokenBucket>
 where
     S: Read + Write,
+    A: RateAlgorithm,
 {
     pub stream: S,
-    pub read_opt: Option<LimiterOptions>,
-    pub write_opt: Option<LimiterOptions>,
-    last_read_check: Option<std::time::Instant>,
-    last_write_check: Option<std::time::Instant>,
-    additionnal_tokens: (u64, u64),
-
-    #[cfg(test)]
-    pub blocking_duration: (Duration, Duration),
+    /// Algorithms limiting the read and write operations with their options,
+    /// None if the operation is performed on the raw stream
+    algorithms: (Option<A>, Option<A>),
+    /// Chains of buckets shared with other limiters, for the read and write operations
+    /// Every level of the chain is charged for each operation
+    shared: (Vec<SharedLink>, Vec<SharedLink>),
+    /// Weight of the limiter when waiting on a shared bucket with other limiters
+    weights: (u64, u64),
+    clock: Arc<dyn Clock>,
+    /// Shared with the `LimiterHandle`s of this Limiter
+    control: Arc<Control>,
+    /// Interrupts the operations once cancelled
+    cancel: Option<CancelToken>,
+    /// Return `WouldBlock` instead of sleeping
+    nonblocking: bool,
+    /// Instants at which the read and write operations that returned `WouldBlock` are worth retrying
+    ready_at: (Option<Instant>, Option<Instant>),
+    /// Called on the events of the operations
+    observer: Option<Arc<dyn LimiterObserver>>,
+    /// Name given by the user to tell the limiters apart in the diagnostics
+    name: Option<String>,
+    /// Handles of the metrics, registered at the first event
+    #[cfg(feature = "metrics")]
+    metrics: OnceLock<LimiterMetrics>,
+    /// Mode set before the Limiter was registered in a `mio::Poll`, restored on deregister
+    #[cfg(feature = "mio")]
+    nonblocking_before_register: Option<bool>,
 }
 
 impl<S> Limiter<S>
@@ -134,290 +520,576 @@ where
         read_opt: Option<LimiterOptions>,
         write_opt: Option<LimiterOptions>,
     ) -> Limiter<S> {
+        Limiter::with_clock(stream, read_opt, write_opt, Arc::new(SystemClock))
+    }
+
+    /// Create a new `Limiter` using the given clock to get the time and to sleep
+    /// Useful to run the throttling on a virtual time with a `ManualClock`
+    pub fn with_clock(
+        stream: S,
+        read_opt: Option<LimiterOptions>,
+        write_opt: Option<LimiterOptions>,
+        clock: Arc<dyn Clock>,
+    ) -> Limiter<S> {
+        let now = clock.now();
+        Limiter::with_algorithm(
+            stream,
+            read_opt.map(|opts| TokenBucket::new(opts, now)),
+            write_opt.map(|opts| TokenBucket::new(opts, now)),
+            clock,
+        )
+    }
+
+    /// Create a new `Limiter` drawing its tokens from buckets shared with other limiters
+    /// The options of the shared buckets (timeout, minimal operation size) are used
+    /// If a bucket is None, the operation will be performed on the raw stream
+    pub fn from_shared(
+        stream: S,
+        read_bucket: Option<Arc<SharedBucket>>,
+        write_bucket: Option<Arc<SharedBucket>>,
+    ) -> Limiter<S> {
+        let clock = match read_bucket.as_ref().or(write_bucket.as_ref()) {
+            Some(bucket) => bucket.clock().clone(),
+            None => Arc::new(SystemClock),
+        };
+        let mut limiter = Limiter::with_clock(stream, None, None, clock);
+        limiter.shared = (
+            read_bucket.into_iter().map(SharedLink::new).collect(),
+            write_bucket.into_iter().map(SharedLink::new).collect(),
+        );
+        limiter
+    }
+}
+
+impl<S, A> Limiter<S, A>
+where
+    S: Read + Write,
+    A: RateAlgorithm,
+{
+    /// Create a new `Limiter` using the given clock, whose operations are limited by the
+    /// given algorithms instead of a `TokenBucket`. They should use the same clock.
+    /// If an algorithm is None, the operation will be performed on the raw stream
+    pub fn with_algorithm(
+        stream: S,
+        read_algorithm: Option<A>,
+        write_algorithm: Option<A>,
+        clock: Arc<dyn Clock>,
+    ) -> Limiter<S, A> {
         Limiter {
             stream,
-            // Instant at which we last performed a read
-            last_read_check: if read_opt.is_some() {
-                Some(std::time::Instant::now())
-            } else {
-                None
-            },
-            // Instant at which we last performed a write
-            last_write_check: if write_opt.is_some() {
-                Some(std::time::Instant::now())
-            } else {
-                None
-            },
-            read_opt,
-            write_opt,
-            additionnal_tokens: (0, 0),
-
-            // For testing and debug purposes
-            #[cfg(test)]
-            blocking_duration: (Duration::ZERO, Duration::ZERO),
+            algorithms: (read_algorithm, write_algorithm),
+            shared: (Vec::new(), Vec::new()),
+            weights: (1, 1),
+            clock,
+            control: Arc::new(Control::default()),
+            cancel: None,
+            nonblocking: false,
+            ready_at: (None, None),
+            observer: None,
+            name: None,
+            #[cfg(feature = "metrics")]
+            metrics: OnceLock::new(),
+            #[cfg(feature = "mio")]
+            nonblocking_before_register: None,
         }
     }
 
+    /// Add a shared bucket to the chain limiting the read operations
+    /// The reads will be limited by the options of the Limiter and by every bucket added
+    pub fn add_read_bucket(&mut self, bucket: Arc<SharedBucket>) {
+        self.shared.0.push(SharedLink::new(bucket));
+    }
+
+    /// Add a shared bucket to the chain limiting the write operations
+    /// The writes will be limited by the options of the Limiter and by every bucket added
+    pub fn add_write_bucket(&mut self, bucket: Arc<SharedBucket>) {
+        self.shared.1.push(SharedLink::new(bucket));
+    }
+
+    /// Sets the weight of the reads when waiting with other limiters on a shared bucket
+    /// The tokens are given to the waiting limiters in proportion to their weights
+    pub fn set_read_weight(&mut self, weight: u64) {
+        assert_ne!(weight, 0);
+        self.weights.0 = weight;
+    }
+
+    /// Sets the weight of the writes when waiting with other limiters on a shared bucket
+    /// The tokens are given to the waiting limiters in proportion to their weights
+    pub fn set_write_weight(&mut self, weight: u64) {
+        assert_ne!(weight, 0);
+        self.weights.1 = weight;
+    }
+
+    /// Change the options limiting the read operations, without resetting the bucket.
+    /// The tokens gathered with the previous options are kept (up to the new bucket size)
+    /// If the options are None, the reads will be performed on the raw stream.
+    /// Options given to unlimited reads need `RateAlgorithm::from_options`
+    pub fn set_read_options(&mut self, read_opt: Option<LimiterOptions>) {
+        self.set_options(Direction::Read, read_opt);
+    }
+
+    /// Change the options limiting the write operations, without resetting the bucket.
+    /// The tokens gathered with the previous options are kept (up to the new bucket size)
+    /// If the options are None, the writes will be performed on the raw stream.
+    /// Options given to unlimited writes need `RateAlgorithm::from_options`
+    pub fn set_write_options(&mut self, write_opt: Option<LimiterOptions>) {
+        self.set_options(Direction::Write, write_opt);
+    }
+
+    /// Change the options of a direction, keeping the state of its algorithm
+    fn set_options(&mut self, dir: Direction, opt: Option<LimiterOptions>) {
+        let now = self.clock.now();
+        let algorithm = by_dir_mut(&mut self.algorithms, dir);
+        *algorithm = match (algorithm.take(), opt) {
+            (Some(mut algorithm), Some(opts)) => {
+                algorithm.set_options(opts, now);
+                Some(algorithm)
+            }
+            // Wasn't limited before, start with an empty bucket like a new Limiter
+            (None, Some(opts)) => A::from_options(opts, now),
+            (_, None) => None,
+        };
+    }
+
+    /// Get the options limiting the read operations, None if they are performed on
+    /// the raw stream (or only limited by shared buckets)
+    pub fn read_options(&self) -> Option<&LimiterOptions> {
+        self.algorithms.0.as_ref().map(A::options)
+    }
+
+    /// Get the options limiting the write operations, None if they are performed on
+    /// the raw stream (or only limited by shared buckets)
+    pub fn write_options(&self) -> Option<&LimiterOptions> {
+        self.algorithms.1.as_ref().map(A::options)
+    }
+
     /// Get the raw stream, deconstruct the Limiter struct.
     pub fn get_stream(self) -> S {
         self.stream
     }
 
-    /// Get the number of bytes available for read / write.
-    fn tokens_available(&self) -> (Option<u64>, Option<u64>) {
-        let read_tokens = if let Some(LimiterOptions {
-            window_length,
-            bucket_size,
-            wtime_ns,
-            ..
-        }) = self.read_opt
-        {
-            // Get the number of nanoseconds since last read
-            let lrc = match u64::try_from(self.last_read_check.unwrap().elapsed().as_nanos()) {
-                Ok(n) => n,
-                // Will cap the last_read_check at a duration of about 584 years
-                Err(_) => u64::MAX,
-            };
-            if wtime_ns == 0 {
-                // If we don't wait at all because of options, we can read u64::MAX bytes at once
-                Some(u64::MAX)
-            } else {
-                // Cross product to get the number of bytes we can read
-                // Add additionnal tokens we had from previous iterations
-                Some(
-                    std::cmp::min(lrc.saturating_mul(window_length) / wtime_ns, bucket_size)
-                        .saturating_add(self.additionnal_tokens.0),
-                )
-            }
-        } else {
-            None
-        };
+    /// Get the clock used by this Limiter
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
 
-        // Same as read operation
-        let write_tokens = if let Some(LimiterOptions {
-            window_length,
-            bucket_size,
-            wtime_ns,
-            ..
-        }) = self.write_opt
+    /// Get a handle to change the options, pause the operations or read the counters
+    /// of this Limiter from another thread
+    pub fn handle(&self) -> LimiterHandle {
+        LimiterHandle {
+            control: self.control.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+
+    /// Sets the observer called on the sleeps, transfers, timeouts and errors of the
+    /// operations, None to remove it. The same observer can be given to many limiters
+    pub fn set_observer(&mut self, observer: Option<Arc<dyn LimiterObserver>>) {
+        self.observer = observer;
+    }
+
+    /// Sets a name for this Limiter, added to the spans of the `tracing` feature and
+    /// to the labels of the `metrics` feature
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        self.name = Some(name.into());
+        // Register the metrics again with the new label
+        #[cfg(feature = "metrics")]
         {
-            let lwc = match u64::try_from(self.last_write_check.unwrap().elapsed().as_nanos()) {
-                Ok(n) => n,
-                Err(_) => u64::MAX,
-            };
-            if wtime_ns == 0 {
-                Some(u64::MAX)
-            } else {
-                Some(
-                    std::cmp::min(lwc.saturating_mul(window_length) / wtime_ns, bucket_size)
-                        .saturating_add(self.additionnal_tokens.1),
-                )
-            }
-        } else {
-            None
-        };
-        (read_tokens, write_tokens)
+            self.metrics = OnceLock::new();
+        }
     }
 
-    /// Get if this Limiter limits the read or write stream (or none)
-    pub fn limits(&self) -> (bool, bool) {
-        (
-            self.read_opt.is_some() && self.last_read_check.is_some(),
-            self.write_opt.is_some() && self.last_write_check.is_some(),
+    /// Get the name of this Limiter, if any
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Span covering an operation, with its direction and the name of the Limiter
+    #[cfg(feature = "tracing")]
+    fn span(&self, dir: Direction) -> tracing::Span {
+        tracing::debug_span!(
+            "limiter",
+            direction = dir.as_str(),
+            name = self.name.as_deref()
         )
     }
 
-    /// Read instantly from the stream, add duration it took to the attribute for debugging
-    #[cfg(test)]
-    pub fn read_instant(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let read_start_instant = std::time::Instant::now();
-        let nb = self.stream.read(buf)?;
-        self.blocking_duration.0 += read_start_instant.elapsed();
-        Ok(nb)
+    /// Call the observer, if any, and export the event to the metrics
+    fn observe(&self, event: impl Fn(&dyn LimiterObserver)) {
+        if let Some(observer) = self.observer.as_deref() {
+            event(observer);
+        }
+        #[cfg(feature = "metrics")]
+        event(
+            self.metrics
+                .get_or_init(|| LimiterMetrics::new(self.name.as_deref())),
+        );
     }
 
-    /// Read instantly from the stream
-    #[cfg(not(test))]
-    pub fn read_instant(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.stream.read(buf)
+    /// Sets a token to interrupt the operations of this Limiter, even while it sleeps.
+    /// The same token can be given to many limiters to stop all of them at once
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        token.register(&self.control);
+        self.cancel = Some(token);
     }
 
-    /// Write instantly from the stream, add duration it took to the attribute for debugging
-    #[cfg(test)]
-    pub fn write_instant(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let write_start_instant = std::time::Instant::now();
-        let nb = self.stream.write(buf)?;
-        self.blocking_duration.1 += write_start_instant.elapsed();
-        Ok(nb)
+    /// Get if the operations of this Limiter were cancelled
+    fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
     }
 
-    /// Write instantly from the stream
-    #[cfg(not(test))]
-    pub fn write_instant(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.stream.write(buf)
+    /// Moves the Limiter into or out of non-blocking mode.
+    /// In non-blocking mode, the operations never sleep: if there are not enough tokens, they
+    /// return the bytes transferred so far, or an error of kind `io::ErrorKind::WouldBlock`.
+    /// `next_ready_at` then tells when to retry.
+    /// A limiter throttled by a shared bucket leaves its queue, so it doesn't hold back the
+    /// other limiters of the bucket if it never retries.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
     }
-}
 
-impl<S> Read for Limiter<S>
-where
-    S: Read + Write,
-{
-    /// Read a stream, limit the I/O operation speed as configured inside the options.
-    /// Supposed to have exactly the same behavior as a "normal" system IO read.
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        // Initialize the algorithm
-        let read_start = Instant::now();
-        let mut read: u64 = 0;
-        let mut buf_left = u64::try_from(buf.len()).expect("R buflen to u64");
-        let Some(opts) = self.read_opt.as_ref() else {
-            // If the stream isn't limited, read instantly instead
-            return self.read_instant(buf);
-        };
+    /// Get the instant at which the operations throttled in non-blocking mode are worth
+    /// retrying (the earliest of the read and write ones), None if none is throttled
+    pub fn next_ready_at(&self) -> Option<Instant> {
+        match self.ready_at {
+            (Some(read), Some(write)) => Some(read.min(write)),
+            (read, write) => read.or(write),
+        }
+    }
 
-        while buf_left > 0 {
-            // Timeout if time since start of algorithm is greater than timeout set in options
-            if let Some(t) = opts.timeout {
-                if read_start.elapsed() > t {
-                    return Err(io::Error::new(io::ErrorKind::TimedOut, "Read timeout"));
-                }
-            }
+    /// Remember when to retry an operation throttled in non-blocking mode, in `tsleep`.
+    /// A retry time too far to be represented is never reached, the limiter isn't woken up.
+    fn would_block(&mut self, dir: Direction, tsleep: Duration) -> io::Error {
+        let ready_at = self.clock.now().checked_add(tsleep);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(?ready_at, "Throttled in non-blocking mode");
+        *by_dir_mut(&mut self.ready_at, dir) = ready_at;
+        dir.error(io::ErrorKind::WouldBlock, "throttled")
+    }
 
-            // Get the number of bytes we can read since last loop
-            let nb_bytes_readable = self.tokens_available().0.unwrap().min(buf_left);
-            // Get the number of bytes under which it's not worth doing a read and we need to sleep instead
-            let sleep_threshold = opts.sleep_threshold.min(buf_left);
+    /// Get the options used to limit an operation, the ones of the first shared bucket
+    /// if the Limiter doesn't have its own
+    fn options(&self, dir: Direction) -> Option<LimiterOptions> {
+        by_dir(&self.algorithms, dir)
+            .as_ref()
+            .map(A::options)
+            .or_else(|| {
+                self.shared_chain(dir)
+                    .first()
+                    .map(|link| link.bucket.options())
+            })
+            .cloned()
+    }
 
-            // If it's not worth reading yet, we sleep and loop back later
-            if nb_bytes_readable < sleep_threshold {
-                // Check how much we need before it's worth reading
-                let nb_left: u32 = sleep_threshold
-                    .saturating_sub(nb_bytes_readable)
-                    .try_into()
-                    .expect("Read nb left > u32::MAX");
+    /// Get the chain of shared buckets limiting a direction
+    fn shared_chain(&self, dir: Direction) -> &[SharedLink] {
+        by_dir::<Vec<_>>(&self.shared, dir)
+    }
 
-                // Compute the time required to get to the number of bytes required
-                let tsleep_total = if let Some(t) = opts.timeout {
-                    (opts.tsleep * nb_left).min(t.saturating_sub(read_start.elapsed()))
-                } else {
-                    opts.tsleep * nb_left
-                };
+    /// Get the chain of shared buckets limiting a direction
+    fn shared_chain_mut(&mut self, dir: Direction) -> &mut [SharedLink] {
+        by_dir_mut::<Vec<_>>(&mut self.shared, dir)
+    }
 
-                std::thread::sleep(tsleep_total);
+    /// Apply the changes requested through the handles, and wait as long as the
+    /// operations are paused (up to the timeout of the operation started at `start`).
+    /// Returns true if the options were changed.
+    fn sync_control(&mut self, dir: Direction, start: Instant) -> io::Result<bool> {
+        let mut changed = false;
+        loop {
+            let generation = self.control.generation();
+            if let Some(pending) = self.control.take_pending() {
+                if let Some(read_opt) = pending.read_opt {
+                    self.set_read_options(read_opt);
+                }
+                if let Some(write_opt) = pending.write_opt {
+                    self.set_write_options(write_opt);
+                }
+                #[cfg(feature = "tracing")]
+                tracing::debug!("Options changed through a handle");
+                changed = true;
+            }
+            if !self.control.is_paused(dir) || self.is_cancelled() {
+                return Ok(changed);
+            }
 
-                // On debug mode, we check that we have MORE bytes to read after sleep
-                #[cfg(debug_assertions)]
-                {
-                    if nb_bytes_readable != opts.bucket_size {
-                        let new_nb_bytes_readable = self.tokens_available().0.unwrap();
-                        debug_assert!(new_nb_bytes_readable > nb_bytes_readable,
-                            "\n{:?}\nTsleep: {:?} x {nb_left} = {:?}\nReadlimit: {}\n{nb_bytes_readable} == {new_nb_bytes_readable}",
-                            self.read_opt.as_ref(),
-                            opts.tsleep,
-                            opts.tsleep * nb_left,
-                            opts.stream_cap_limit,
-                        );
+            // Leave the queues of the shared buckets, we don't want to hold the others back
+            shared::cancel_chain(self.shared_chain_mut(dir));
+            if self.nonblocking {
+                return Err(self.would_block(dir, PAUSE_CHECK));
+            }
+            let tsleep = match self.options(dir).and_then(|opts| opts.timeout) {
+                Some(t) => {
+                    let elapsed = self.clock.now().saturating_duration_since(start);
+                    if elapsed >= t {
+                        self.control.stats(dir).add_timeout();
+                        self.observe(|observer| observer.on_timeout(dir, elapsed));
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(?elapsed, "Timeout while paused");
+                        return Err(dir.error(io::ErrorKind::TimedOut, "timeout"));
                     }
+                    t - elapsed
                 }
-                continue;
-            }
+                None => PAUSE_CHECK,
+            };
+            #[cfg(feature = "tracing")]
+            tracing::trace!(?tsleep, "Paused");
+            self.control.sleep(dir, &*self.clock, generation, tsleep);
+        }
+    }
 
-            // Before reading so that we don't count the time it takes to read
-            self.last_read_check = Some(std::time::Instant::now());
+    /// Get the number of bytes the algorithm of a direction allows at `now`,
+    /// u64::MAX if the Limiter doesn't have its own options
+    fn available(&self, dir: Direction, now: Instant) -> u64 {
+        by_dir(&self.algorithms, dir)
+            .as_ref()
+            .map_or(u64::MAX, |algorithm| algorithm.available(now))
+    }
 
-            // Compute the indexes of the start / end on our buffer
-            let read_start = usize::try_from(read).expect("R read_start to usize");
-            let read_end = usize::try_from(read.saturating_add(nb_bytes_readable.min(buf_left)))
-                .expect("R read_end to usize");
+    /// Get the time to wait from `now` until the algorithm of a direction allows `nb` bytes
+    fn time_until(&self, dir: Direction, nb: u64, now: Instant) -> Duration {
+        by_dir(&self.algorithms, dir)
+            .as_ref()
+            .map_or(Duration::ZERO, |algorithm| algorithm.time_until(nb, now))
+    }
 
-            // For debugging stats in tests
-            #[cfg(test)]
-            let read_start_instant = std::time::Instant::now();
+    /// Get the limit of the algorithm of a direction making `nb` bytes wait from `now`
+    fn constraint(&self, dir: Direction, nb: u64, now: Instant) -> Constraint {
+        by_dir(&self.algorithms, dir)
+            .as_ref()
+            .map_or(Constraint::Bytes, |algorithm| algorithm.constraint(nb, now))
+    }
 
-            let read_now = u64::try_from(self.stream.read(&mut buf[read_start..read_end])?)
-                .expect("R read_now to u64");
+    /// Spend the `used` bytes of an operation allowed at `start` and done at `now`
+    fn consume(&mut self, dir: Direction, used: u64, start: Instant, now: Instant) {
+        if let Some(algorithm) = by_dir_mut(&mut self.algorithms, dir) {
+            algorithm.consume(used, start, now);
+        }
+    }
 
-            // Add duration of the read operation in the stats for debugging in tests
-            #[cfg(test)]
-            {
-                self.blocking_duration.0 += read_start_instant.elapsed();
-            }
+    /// Get if this Limiter limits the read or write stream (or none)
+    pub fn limits(&self) -> (bool, bool) {
+        (
+            self.algorithms.0.is_some() || !self.shared.0.is_empty(),
+            self.algorithms.1.is_some() || !self.shared.1.is_empty(),
+        )
+    }
 
-            // If we haven't spent all of our tokens yet, add the rest to the additionnal_tokens
-            self.additionnal_tokens = (
-                nb_bytes_readable.saturating_sub(read_now),
-                self.additionnal_tokens.1,
-            );
+    /// Get the statistics of the read and write operations
+    pub fn stats(&self) -> (LimiterStats, LimiterStats) {
+        (
+            self.control.stats(Direction::Read).snapshot(),
+            self.control.stats(Direction::Write).snapshot(),
+        )
+    }
 
-            read = read.saturating_add(read_now);
-            buf_left = buf_left.saturating_sub(read_now);
+    /// Change the horizons over which the throughput is averaged (`DEFAULT_HORIZONS`
+    /// by default), the averages start again from 0
+    pub fn set_throughput_horizons(&mut self, horizons: &[Duration]) {
+        self.control.set_horizons(horizons);
+    }
 
-            // If there's nothing left to read, stop the process
-            if read_now == 0 {
-                break;
-            }
-        }
+    /// Get the rates achieved by the read and write operations, in bytes per second,
+    /// averaged over each horizon
+    pub fn throughput(&self) -> (Rates, Rates) {
+        self.control.throughput(self.clock.now())
+    }
 
-        self.last_read_check = Some(std::time::Instant::now());
-        Ok(usize::try_from(read).expect("R return to usize"))
+    /// Get the rates achieved by the read and write operations as a fraction of the
+    /// rate allowed by their options (`window_length / window_time`), over each horizon.
+    /// None for a direction that isn't limited. Close to 1 when the limit is saturating.
+    pub fn utilization(&self) -> (Option<Rates>, Option<Rates>) {
+        let (read, write) = self.throughput();
+        let fraction = |dir: Direction, rates: Rates| {
+            self.options(dir).map(|opts| {
+                let limit = opts.window_length as f64 / opts.window_time.as_secs_f64();
+                rates
+                    .into_iter()
+                    .map(|(horizon, rate)| (horizon, rate / limit))
+                    .collect()
+            })
+        };
+        (
+            fraction(Direction::Read, read),
+            fraction(Direction::Write, write),
+        )
     }
-}
 
-impl<S> Write for Limiter<S>
-where
-    S: Read + Write,
-{
-    /// Write a stream, limit the I/O operation speed as configured inside the options.
-    /// Supposed to have exactly the same behavior as a "normal" system IO write.
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    /// Read instantly from the stream
+    pub fn read_instant(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.instant_io(Direction::Read, |stream| stream.read(buf))
+    }
+
+    /// Write instantly from the stream
+    pub fn write_instant(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.instant_io(Direction::Write, |stream| stream.write(buf))
+    }
+
+    /// Do an operation on the raw stream, counting it in the stats and the events
+    fn instant_io(
+        &mut self,
+        dir: Direction,
+        io: impl FnOnce(&mut S) -> io::Result<usize>,
+    ) -> io::Result<usize> {
+        let io_start = self.clock.now();
+        let res = io(&mut self.stream);
+        let nb = u64::try_from(*res.as_ref().unwrap_or(&0)).expect("nb to u64");
+        let now = self.clock.now();
+        self.control
+            .add_io(dir, nb, now.saturating_duration_since(io_start), now);
+        #[cfg(feature = "tracing")]
+        match res.as_ref() {
+            Ok(_) => tracing::trace!(bytes = nb, "Unlimited {}", dir.as_str()),
+            Err(e) => tracing::debug!(error = %e, "Inner {} failed", dir.as_str()),
+        }
+        match res.as_ref() {
+            Ok(_) => self.observe(|observer| observer.on_transfer(dir, nb)),
+            Err(e) => self.observe(|observer| observer.on_io_error(dir, e)),
+        }
+        res
+    }
+
+    /// Transfer up to `len` bytes, limiting the operations of the direction as
+    /// configured inside the options. `io` transfers a range of the buffer on the stream.
+    fn transfer(
+        &mut self,
+        dir: Direction,
+        len: usize,
+        mut io: impl FnMut(&mut S, Range<usize>) -> io::Result<usize>,
+    ) -> io::Result<usize> {
         // Initialize the algorithm
-        let write_start = Instant::now();
-        let mut write: u64 = 0;
-        let mut buf_left = u64::try_from(buf.len()).expect("W buflen to u64");
-        let Some(opts) = self.write_opt.as_ref() else {
-            // If the stream isn't limited, write instantly instead
-            return self.write_instant(buf);
+        #[cfg(feature = "tracing")]
+        let _span = self.span(dir).entered();
+        let op_start = self.clock.now();
+        let mut done: u64 = 0;
+        let mut buf_left = u64::try_from(len).expect("buflen to u64");
+        *by_dir_mut(&mut self.ready_at, dir) = None;
+        self.control.stats(dir).add_call();
+        // Count the call once in the stats if it has to wait for tokens, or for operations
+        let mut throttled = false;
+        let mut ops_throttled = false;
+        // Apply the changes made through the handles, wait if the operations are paused
+        self.sync_control(dir, op_start)?;
+        if self.is_cancelled() {
+            return Err(Cancelled::error(dir));
+        }
+        let Some(mut opts) = self.options(dir) else {
+            // If the stream isn't limited, transfer instantly instead
+            return self.instant_io(dir, |stream| io(stream, 0..len));
         };
 
         while buf_left > 0 {
+            // Read first, so a change made through a handle while we compute
+            // the tokens still interrupts our sleep
+            let generation = self.control.generation();
+            if self.sync_control(dir, op_start)? {
+                match self.options(dir) {
+                    Some(new_opts) => opts = new_opts,
+                    // The limit was removed, transfer the rest on the raw stream
+                    None => {
+                        let start = usize::try_from(done).expect("start to usize");
+                        let done_now = self.instant_io(dir, |stream| io(stream, start..len))?;
+                        done =
+                            done.saturating_add(u64::try_from(done_now).expect("done_now to u64"));
+                        break;
+                    }
+                }
+            }
+
+            // Stop there if cancelled, we return what was transferred so far
+            if self.is_cancelled() {
+                shared::cancel_chain(self.shared_chain_mut(dir));
+                if done > 0 {
+                    break;
+                }
+                return Err(Cancelled::error(dir));
+            }
+
             // Timeout if time since start of algorithm is greater than timeout set in options
             if let Some(t) = opts.timeout {
-                if write_start.elapsed() > t {
-                    return Err(io::Error::new(io::ErrorKind::TimedOut, "Write timeout"));
+                let elapsed = self.clock.now().saturating_duration_since(op_start);
+                if elapsed >= t {
+                    // Leave the queues of the shared buckets we were waiting on
+                    shared::cancel_chain(self.shared_chain_mut(dir));
+                    self.control.stats(dir).add_timeout();
+                    self.observe(|observer| observer.on_timeout(dir, elapsed));
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(?elapsed, done, "Timeout");
+                    return Err(dir.error(io::ErrorKind::TimedOut, "timeout"));
                 }
             }
 
-            // Get the number of bytes we can write since last loop
-            let nb_bytes_writable = self.tokens_available().1.unwrap().min(buf_left);
-            // Get the number of bytes under which it's not worth doing a write and we need to sleep instead
+            // Get the number of bytes we can transfer since last loop
+            let nb_bytes_allowed = self.available(dir, self.clock.now()).min(buf_left);
+            // Get the number of bytes under which it's not worth doing an operation and we need to sleep instead
             let sleep_threshold = opts.sleep_threshold.min(buf_left);
+            #[cfg(feature = "tracing")]
+            tracing::trace!(
+                tokens = nb_bytes_allowed,
+                sleep_threshold,
+                buf_left,
+                "Tokens available"
+            );
 
-            // If it's not worth writing yet, we sleep and loop back later
-            if nb_bytes_writable < sleep_threshold {
-                // Check how much we need before it's worth writing
-                let nb_left: u32 = sleep_threshold
-                    .saturating_sub(nb_bytes_writable)
-                    .try_into()
-                    .expect("Write nb left > u32::MAX");
+            // If it's not worth transferring yet, we sleep and loop back later
+            if nb_bytes_allowed < sleep_threshold {
+                // Check how much we need before it's worth transferring
+                let nb_left = sleep_threshold.saturating_sub(nb_bytes_allowed);
+                let tsleep = self.time_until(dir, sleep_threshold, self.clock.now());
+                let constraint = self.constraint(dir, sleep_threshold, self.clock.now());
+
+                self.control.stats(dir).add_throttled(&mut throttled);
+                if constraint == Constraint::Operations {
+                    self.control
+                        .stats(dir)
+                        .add_ops_throttled(&mut ops_throttled);
+                }
+                // Don't sleep in non-blocking mode, return what was transferred so far
+                if self.nonblocking {
+                    if done > 0 {
+                        return Ok(usize::try_from(done).expect("return to usize"));
+                    }
+                    return Err(self.would_block(dir, tsleep));
+                }
 
                 // Compute the time required to get to the number of bytes required
                 let tsleep_total = if let Some(t) = opts.timeout {
-                    (opts.tsleep * nb_left).min(t.saturating_sub(write_start.elapsed()))
+                    tsleep
+                        .min(t.saturating_sub(self.clock.now().saturating_duration_since(op_start)))
                 } else {
-                    opts.tsleep * nb_left
+                    tsleep
                 };
 
-                std::thread::sleep(tsleep_total);
+                self.observe(|observer| {
+                    observer.on_throttle(dir, constraint);
+                    observer.on_sleep(dir, tsleep_total, nb_left);
+                });
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    tsleep = ?tsleep_total,
+                    tokens = nb_left,
+                    constraint = constraint.as_str(),
+                    "Sleeping for tokens"
+                );
+                // Wake up early if something is changed through a handle
+                let _interrupted = self
+                    .control
+                    .sleep(dir, &*self.clock, generation, tsleep_total);
 
-                // On debug mode, we check that we have MORE bytes to write after sleep
+                // On debug mode, we check that we have MORE bytes to transfer after sleep
                 #[cfg(debug_assertions)]
                 {
-                    if nb_bytes_writable != opts.bucket_size {
-                        let new_nb_bytes_writable = self.tokens_available().1.unwrap();
+                    // Skip the check if the sleep was shortened by the timeout
+                    if nb_bytes_allowed != opts.capacity()
+                        && !_interrupted
+                        && tsleep_total == tsleep
+                    {
+                        let new_nb_bytes_allowed = self.available(dir, self.clock.now());
                         debug_assert!(
-                            new_nb_bytes_writable > nb_bytes_writable,
-                            "\n{:?}\nTsleep: {:?}\nWritelimit: {}\n",
-                            self.write_opt.as_ref(),
-                            opts.tsleep,
+                            new_nb_bytes_allowed > nb_bytes_allowed,
+                            "\n{:?}\nTsleep: {:?}\nLimit: {}\n{nb_bytes_allowed} == {new_nb_bytes_allowed}",
+                            opts,
+                            tsleep,
                             opts.stream_cap_limit,
                         );
                     }
@@ -425,44 +1097,142 @@ where
                 continue;
             }
 
-            // Before writing so that we don't count the time it takes to write
-            self.last_write_check = Some(std::time::Instant::now());
-
-            // Compute the indexes of the start / end on our buffer
-            let write_start = usize::try_from(write).expect("W write_start to usize");
-            let write_end = usize::try_from(write.saturating_add(nb_bytes_writable.min(buf_left)))
-                .expect("W write_end to usize");
-
-            // For debugging stats in tests
-            #[cfg(test)]
-            let write_start_instant = std::time::Instant::now();
+            // Take the tokens from every shared bucket of the chain. If other limiters spent them
+            // we wait for the most restrictive bucket to refill, or for some tokens to be given back
+            let nb_bytes_taken = if self.shared_chain(dir).is_empty() {
+                nb_bytes_allowed
+            } else {
+                let weight = *by_dir(&self.weights, dir);
+                match shared::take_chain(
+                    self.shared_chain_mut(dir),
+                    weight,
+                    sleep_threshold,
+                    nb_bytes_allowed,
+                ) {
+                    Ok(nb) => nb,
+                    Err((bucket, tsleep, bucket_generation)) => {
+                        self.control.stats(dir).add_throttled(&mut throttled);
+                        // Leave the queues, nothing tells us the operation will be retried
+                        // and we don't want to hold the others back meanwhile
+                        if self.nonblocking {
+                            shared::cancel_chain(self.shared_chain_mut(dir));
+                            if done > 0 {
+                                return Ok(usize::try_from(done).expect("return to usize"));
+                            }
+                            return Err(self.would_block(dir, tsleep));
+                        }
+                        let tsleep_total = if let Some(t) = opts.timeout {
+                            tsleep.min(t.saturating_sub(
+                                self.clock.now().saturating_duration_since(op_start),
+                            ))
+                        } else {
+                            tsleep
+                        };
+                        self.observe(|observer| {
+                            observer.on_sleep(dir, tsleep_total, sleep_threshold)
+                        });
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(
+                            tsleep = ?tsleep_total,
+                            tokens = sleep_threshold,
+                            bucket,
+                            "Waiting on a shared bucket"
+                        );
+                        self.control.wait_shared(
+                            dir,
+                            &*self.clock,
+                            self.shared_chain(dir)[bucket].bucket.signal(),
+                            bucket_generation,
+                            generation,
+                            tsleep_total,
+                        );
+                        continue;
+                    }
+                }
+            };
 
-            let write_now = u64::try_from(self.stream.write(&buf[write_start..write_end])?)
-                .expect("W write_now_ to u64");
+            // Before the operation so that we don't count the time it takes
+            let check = self.clock.now();
 
-            // Add duration of the write operation in the stats for debugging in tests
-            #[cfg(test)]
-            {
-                self.blocking_duration.1 += write_start_instant.elapsed();
-            }
+            // Compute the indexes of the start / end on our buffer
+            let start = usize::try_from(done).expect("start to usize");
+            let end = usize::try_from(done.saturating_add(nb_bytes_taken)).expect("end to usize");
 
-            // If we haven't spent all of our tokens yet, add the rest to the additionnal_tokens
-            self.additionnal_tokens = (
-                self.additionnal_tokens.0,
-                nb_bytes_writable.saturating_sub(write_now),
+            let io_res = io(&mut self.stream, start..end);
+            let io_end = self.clock.now();
+            let io_time = io_end.saturating_duration_since(check);
+            let done_now = match io_res {
+                Ok(n) => u64::try_from(n).expect("done_now to u64"),
+                Err(e) => {
+                    self.control.add_io(dir, 0, io_time, io_end);
+                    self.observe(|observer| observer.on_io_error(dir, &e));
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(error = %e, "Inner {} failed", dir.as_str());
+                    shared::refund_chain(self.shared_chain(dir), nb_bytes_taken);
+                    // Keep the tokens we didn't spend
+                    self.consume(dir, 0, check, io_end);
+                    // A non-blocking stream may not be ready for the rest, return what was transferred so far
+                    if e.kind() == io::ErrorKind::WouldBlock && done > 0 {
+                        break;
+                    }
+                    return Err(e);
+                }
+            };
+            // Give back to the shared buckets the tokens we didn't use
+            shared::refund_chain(
+                self.shared_chain(dir),
+                nb_bytes_taken.saturating_sub(done_now),
             );
+            self.control.add_io(dir, done_now, io_time, io_end);
+            self.observe(|observer| observer.on_transfer(dir, done_now));
+            #[cfg(feature = "tracing")]
+            tracing::trace!(
+                bytes = done_now,
+                tokens = nb_bytes_taken,
+                ?io_time,
+                "Chunk transferred"
+            );
+
+            // Spend the tokens used by the operation, the algorithm keeps the rest
+            self.consume(dir, done_now, check, io_end);
 
-            write = write.saturating_add(write_now);
-            buf_left = buf_left.saturating_sub(write_now);
+            done = done.saturating_add(done_now);
+            buf_left = buf_left.saturating_sub(done_now);
 
-            // If there's nothing left to write, stop the process
-            if write_now == 0 {
+            // If there's nothing left to transfer, stop the process
+            if done_now == 0 {
                 break;
             }
         }
+        Ok(usize::try_from(done).expect("return to usize"))
+    }
+}
 
-        self.last_write_check = Some(std::time::Instant::now());
-        Ok(usize::try_from(write).expect("W return to usize"))
+impl<S, A> Read for Limiter<S, A>
+where
+    S: Read + Write,
+    A: RateAlgorithm,
+{
+    /// Read a stream, limit the I/O operation speed as configured inside the options.
+    /// Supposed to have exactly the same behavior as a "normal" system IO read.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.transfer(Direction::Read, buf.len(), |stream, range| {
+            stream.read(&mut buf[range])
+        })
+    }
+}
+
+impl<S, A> Write for Limiter<S, A>
+where
+    S: Read + Write,
+    A: RateAlgorithm,
+{
+    /// Write a stream, limit the I/O operation speed as configured inside the options.
+    /// Supposed to have exactly the same behavior as a "normal" system IO write.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.transfer(Direction::Write, buf.len(), |stream, range| {
+            stream.write(&buf[range])
+        })
     }
 
     /// Flush the underlying stream
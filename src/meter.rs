@@ -0,0 +1,89 @@
+//! Live estimation of the throughput achieved by a `Limiter`, as exponentially
+//! weighted moving averages over several horizons.
+use std::time::{Duration, Instant};
+
+/// Horizons of the moving averages of a `Limiter`, unless changed with
+/// `Limiter::set_throughput_horizons`
+pub const DEFAULT_HORIZONS: [Duration; 3] = [
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+];
+
+/// Average rates in bytes per second, with the horizon of each average
+pub type Rates = Vec<(Duration, f64)>;
+
+/// Exponentially weighted moving averages of a rate, in bytes per second.
+/// Each transfer of `n` bytes adds `n / horizon` to the average of a horizon, which
+/// then decays by a factor `e` every `horizon`. A constant rate is reached by the
+/// average after a few horizons, starting from 0.
+#[derive(Clone, Debug)]
+pub struct ThroughputMeter {
+    /// Horizon of each average, with its value at `last_update`
+    averages: Vec<(Duration, f64)>,
+    last_update: Option<Instant>,
+}
+
+impl Default for ThroughputMeter {
+    fn default() -> ThroughputMeter {
+        ThroughputMeter::new(&DEFAULT_HORIZONS)
+    }
+}
+
+impl ThroughputMeter {
+    /// Create a new meter averaging the rate over each of the given horizons
+    pub fn new(horizons: &[Duration]) -> ThroughputMeter {
+        assert!(
+            horizons.iter().all(|horizon| !horizon.is_zero()),
+            "Horizons of a ThroughputMeter can't be zero"
+        );
+        ThroughputMeter {
+            averages: horizons.iter().map(|horizon| (*horizon, 0.0)).collect(),
+            last_update: None,
+        }
+    }
+
+    /// Get the horizons of the averages
+    pub fn horizons(&self) -> Vec<Duration> {
+        self.averages.iter().map(|(horizon, _)| *horizon).collect()
+    }
+
+    /// Count `bytes` transferred at `now`
+    pub fn record(&mut self, bytes: u64, now: Instant) {
+        let elapsed = self.elapsed(now);
+        for (horizon, rate) in self.averages.iter_mut() {
+            *rate = decay(*rate, elapsed, *horizon) + bytes as f64 / horizon.as_secs_f64();
+        }
+        // Keep the latest instant if the clock went backward
+        self.last_update = Some(self.last_update.map_or(now, |last| last.max(now)));
+    }
+
+    /// Get the average rate of each horizon at `now`, in bytes per second
+    pub fn rates(&self, now: Instant) -> Rates {
+        let elapsed = self.elapsed(now);
+        self.averages
+            .iter()
+            .map(|(horizon, rate)| (*horizon, decay(*rate, elapsed, *horizon)))
+            .collect()
+    }
+
+    /// Get the average rate over the given horizon at `now`, in bytes per second.
+    /// None if the meter doesn't average over this horizon
+    pub fn rate(&self, horizon: Duration, now: Instant) -> Option<f64> {
+        let elapsed = self.elapsed(now);
+        self.averages
+            .iter()
+            .find(|(h, _)| *h == horizon)
+            .map(|(h, rate)| decay(*rate, elapsed, *h))
+    }
+
+    fn elapsed(&self, now: Instant) -> Duration {
+        self.last_update
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last))
+    }
+}
+
+/// Apply the decay of an average over the elapsed time
+fn decay(rate: f64, elapsed: Duration, horizon: Duration) -> f64 {
+    rate * (-elapsed.as_secs_f64() / horizon.as_secs_f64()).exp()
+}
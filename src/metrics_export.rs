@@ -0,0 +1,87 @@
+//! Export of the events of a `Limiter` to the `metrics` facade, enabled with the
+//! `metrics` feature. Every metric is labelled with the `name` of the limiter (empty
+//! if it has none) and the `direction` of the operation:
+//! - `stream_limiter_bytes`: counter of the bytes transferred
+//! - `stream_limiter_sleeps`: counter of the sleeps waiting for tokens
+//! - `stream_limiter_timeouts`: counter of the operations that timed out
+//! - `stream_limiter_io_errors`: counter of the errors of the inner stream
+//! - `stream_limiter_sleep_seconds`: histogram of the durations of the sleeps
+//! - `stream_limiter_chunk_bytes`: histogram of the sizes of the inner operations
+use std::io;
+use std::time::Duration;
+
+use metrics::{Counter, Histogram, Label};
+
+use crate::{Direction, LimiterObserver};
+
+/// Handles of the metrics of one direction, registered once
+struct DirectionMetrics {
+    bytes: Counter,
+    sleeps: Counter,
+    timeouts: Counter,
+    io_errors: Counter,
+    sleep_duration: Histogram,
+    chunk_size: Histogram,
+}
+
+impl DirectionMetrics {
+    fn new(name: &str, dir: Direction) -> DirectionMetrics {
+        let labels = vec![
+            Label::new("name", name.to_string()),
+            Label::new("direction", dir.as_str()),
+        ];
+        DirectionMetrics {
+            bytes: metrics::counter!("stream_limiter_bytes", labels.clone()),
+            sleeps: metrics::counter!("stream_limiter_sleeps", labels.clone()),
+            timeouts: metrics::counter!("stream_limiter_timeouts", labels.clone()),
+            io_errors: metrics::counter!("stream_limiter_io_errors", labels.clone()),
+            sleep_duration: metrics::histogram!("stream_limiter_sleep_seconds", labels.clone()),
+            chunk_size: metrics::histogram!("stream_limiter_chunk_bytes", labels),
+        }
+    }
+}
+
+/// Metrics of a limiter, registered in the recorder installed at its first event
+pub(crate) struct LimiterMetrics(DirectionMetrics, DirectionMetrics);
+
+impl LimiterMetrics {
+    pub(crate) fn new(name: Option<&str>) -> LimiterMetrics {
+        let name = name.unwrap_or_default();
+        LimiterMetrics(
+            DirectionMetrics::new(name, Direction::Read),
+            DirectionMetrics::new(name, Direction::Write),
+        )
+    }
+
+    fn dir(&self, dir: Direction) -> &DirectionMetrics {
+        match dir {
+            Direction::Read => &self.0,
+            Direction::Write => &self.1,
+        }
+    }
+}
+
+impl LimiterObserver for LimiterMetrics {
+    fn on_sleep(&self, dir: Direction, duration: Duration, _tokens: u64) {
+        let metrics = self.dir(dir);
+        metrics.sleeps.increment(1);
+        metrics.sleep_duration.record(duration);
+    }
+
+    fn on_transfer(&self, dir: Direction, bytes: u64) {
+        // The end of the stream isn't a chunk
+        if bytes > 0 {
+            let metrics = self.dir(dir);
+            metrics.bytes.increment(bytes);
+            metrics.chunk_size.record(bytes as f64);
+        }
+    }
+
+    fn on_timeout(&self, dir: Direction, _elapsed: Duration) {
+        self.dir(dir).timeouts.increment(1);
+    }
+
+    fn on_io_error(&self, dir: Direction, _error: &io::Error) {
+        self.dir(dir).io_errors.increment(1);
+    }
+}
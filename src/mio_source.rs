@@ -0,0 +1,64 @@
+//! Integration with mio, enabled with the `mio` feature.
+//! A `Limiter` wrapping a mio stream can be registered in a `mio::Poll`, its throttled
+//! operations then return `WouldBlock` instead of sleeping.
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use mio::event::Source;
+use mio::{Interest, Registry, Token};
+
+use crate::{Limiter, RateAlgorithm};
+
+/// Registering the Limiter puts it in non-blocking mode, so a throttled operation returns
+/// `WouldBlock` with a deadline given by `Limiter::next_ready_at`. Deregistering it
+/// restores the mode it had before.
+/// No event is generated once this deadline is passed, it has to be folded in the timeout
+/// of `mio::Poll::poll` with `poll_timeout`, and the operation retried after it.
+impl<S, A> Source for Limiter<S, A>
+where
+    S: Read + Write + Source,
+    A: RateAlgorithm,
+{
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.stream.register(registry, token, interests)?;
+        self.nonblocking_before_register = Some(self.nonblocking);
+        self.set_nonblocking(true);
+        Ok(())
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.stream.reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        if let Some(nonblocking) = self.nonblocking_before_register.take() {
+            self.set_nonblocking(nonblocking);
+        }
+        self.stream.deregister(registry)
+    }
+}
+
+/// Get the timeout to give to `mio::Poll::poll` so it returns once the first of the
+/// throttled limiters is ready, or after `timeout` if it's shorter.
+/// `ready_at` are the deadlines of the limiters, from `Limiter::next_ready_at`.
+pub fn poll_timeout<I>(ready_at: I, timeout: Option<Duration>, now: Instant) -> Option<Duration>
+where
+    I: IntoIterator<Item = Option<Instant>>,
+{
+    ready_at
+        .into_iter()
+        .flatten()
+        .map(|deadline| deadline.saturating_duration_since(now))
+        .chain(timeout)
+        .min()
+}
@@ -0,0 +1,30 @@
+//! Hooks called by a `Limiter` on the events of its read and write operations,
+//! to plug logging, metrics or peer scoring without changing the limiter itself.
+use std::io;
+use std::time::Duration;
+
+use crate::{Constraint, Direction};
+
+/// Receives the events of a `Limiter`, set it with `Limiter::set_observer`.
+/// The methods are called from the thread doing the operation, inside the read or
+/// the write, so they should return quickly. They do nothing by default.
+pub trait LimiterObserver: Send + Sync {
+    /// The operation is about to sleep for `duration` to obtain `tokens` more tokens.
+    /// When waiting on a shared bucket, `tokens` is the number of tokens the operation
+    /// asks to the bucket.
+    fn on_sleep(&self, _dir: Direction, _duration: Duration, _tokens: u64) {}
+
+    /// The operation has to wait on the `constraint` of its options, called before
+    /// `on_sleep` when the Limiter has its own options
+    fn on_throttle(&self, _dir: Direction, _constraint: Constraint) {}
+
+    /// `bytes` were transferred by an operation on the inner stream
+    fn on_transfer(&self, _dir: Direction, _bytes: u64) {}
+
+    /// The operation timed out after `elapsed`
+    fn on_timeout(&self, _dir: Direction, _elapsed: Duration) {}
+
+    /// An operation on the inner stream failed, including with `WouldBlock` for a
+    /// non-blocking stream
+    fn on_io_error(&self, _dir: Direction, _error: &io::Error) {}
+}
@@ -0,0 +1,380 @@
+//! Drives many non-blocking limited streams from a single thread, enabled with the
+//! `mio` feature. Each stream is read as soon as both its socket and its bucket allow
+//! it, and the data given to `LimiterScheduler::send` is written the same way.
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use mio::event::Source;
+use mio::{Events, Interest, Poll, Token};
+
+use crate::{poll_timeout, Limiter, RateAlgorithm, TokenBucket};
+
+/// Size of the buffer used to read the streams
+const READ_BUF_SIZE: usize = 16 * 1024;
+
+/// Identifier of a stream added to a `LimiterScheduler`.
+/// The place of a removed stream is reused, its generation tells the streams apart so
+/// the identifier of a removed stream never refers to another one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    index: usize,
+    generation: u64,
+}
+
+/// What happened on the streams during `LimiterScheduler::run_once`
+#[derive(Debug)]
+pub enum SchedulerEvent {
+    /// Data read from a stream
+    Data(StreamId, Vec<u8>),
+    /// The stream reached its end and the data queued on it was written, it was
+    /// removed from the scheduler
+    Closed(StreamId),
+    /// An operation on the stream failed, it was removed from the scheduler
+    Error(StreamId, io::Error),
+}
+
+struct Entry<S, A>
+where
+    S: Read + Write,
+    A: RateAlgorithm,
+{
+    limiter: Limiter<S, A>,
+    /// The socket may have data to read, since the last readable event
+    readable: bool,
+    /// The socket may accept data, since the last writable event
+    writable: bool,
+    /// Data waiting to be written
+    output: Vec<u8>,
+    /// The end of the stream was read, it's removed once its output is written
+    read_closed: bool,
+    /// Instant at which the throttled operations are worth retrying
+    deadline: Option<Instant>,
+}
+
+/// Place of a stream in the scheduler
+struct Slot<S, A>
+where
+    S: Read + Write,
+    A: RateAlgorithm,
+{
+    /// Incremented each time the stream of the slot is removed
+    generation: u64,
+    entry: Option<Entry<S, A>>,
+}
+
+/// Owns many limited streams and drives them with one poll loop, instead of one thread
+/// per stream sleeping in `Limiter::read` / `Limiter::write`.
+/// The deadlines of the throttled streams are kept in a heap, and fold in the timeout
+/// of the poll so they are retried as soon as their bucket permits it.
+pub struct LimiterScheduler<S, A = TokenBucket>
+where
+    S: Read + Write + Source,
+    A: RateAlgorithm,
+{
+    poll: Poll,
+    events: Events,
+    slots: Vec<Slot<S, A>>,
+    /// Indexes of `slots` that can be reused
+    free: Vec<usize>,
+    /// Deadlines of the throttled streams, the earliest first
+    timers: BinaryHeap<Reverse<(Instant, StreamId)>>,
+}
+
+impl<S, A> LimiterScheduler<S, A>
+where
+    S: Read + Write + Source,
+    A: RateAlgorithm,
+{
+    /// Create a new scheduler, without any stream
+    pub fn new() -> io::Result<LimiterScheduler<S, A>> {
+        Ok(LimiterScheduler {
+            poll: Poll::new()?,
+            events: Events::with_capacity(1024),
+            slots: Vec::new(),
+            free: Vec::new(),
+            timers: BinaryHeap::new(),
+        })
+    }
+
+    /// Add a limited stream to the scheduler, it is put in non-blocking mode
+    pub fn add(&mut self, mut limiter: Limiter<S, A>) -> io::Result<StreamId> {
+        let index = self.free.pop().unwrap_or(self.slots.len());
+        self.poll.registry().register(
+            &mut limiter,
+            Token(index),
+            Interest::READABLE | Interest::WRITABLE,
+        )?;
+        let entry = Entry {
+            limiter,
+            readable: false,
+            writable: false,
+            output: Vec::new(),
+            read_closed: false,
+            deadline: None,
+        };
+        if index == self.slots.len() {
+            self.slots.push(Slot {
+                generation: 0,
+                entry: None,
+            });
+        }
+        let slot = &mut self.slots[index];
+        slot.entry = Some(entry);
+        Ok(StreamId {
+            index,
+            generation: slot.generation,
+        })
+    }
+
+    /// Remove a stream from the scheduler, the data not written yet is dropped.
+    /// The limiter is given back in the mode it had before being added.
+    pub fn remove(&mut self, id: StreamId) -> Option<Limiter<S, A>> {
+        let slot = self.slots.get_mut(id.index)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        let mut entry = slot.entry.take()?;
+        slot.generation += 1;
+        self.free.push(id.index);
+        // The stream is dropped by the caller anyway if this fails
+        let _ = self.poll.registry().deregister(&mut entry.limiter);
+        Some(entry.limiter)
+    }
+
+    /// Get the limited stream
+    pub fn get(&self, id: StreamId) -> Option<&Limiter<S, A>> {
+        self.entry(id).map(|entry| &entry.limiter)
+    }
+
+    /// Get the limited stream, for example to change its options
+    pub fn get_mut(&mut self, id: StreamId) -> Option<&mut Limiter<S, A>> {
+        self.entry_mut(id).map(|entry| &mut entry.limiter)
+    }
+
+    /// Queue data to write on a stream, it's written during the next runs as fast as
+    /// its limit allows it. Returns false if the stream isn't in the scheduler.
+    /// Data can still be sent in answer to the last data read before the end of the
+    /// stream, until the next run.
+    pub fn send(&mut self, id: StreamId, data: &[u8]) -> bool {
+        match self.entry_mut(id) {
+            Some(entry) => {
+                entry.output.extend_from_slice(data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get the number of bytes queued on a stream and not written yet
+    pub fn pending_output(&self, id: StreamId) -> usize {
+        self.entry(id).map_or(0, |entry| entry.output.len())
+    }
+
+    /// Get the number of streams in the scheduler
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    /// Get if there is no stream in the scheduler
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Wait for the sockets to be ready or for the throttled streams to get their tokens
+    /// (at most `timeout`), then read and write every stream that can be.
+    pub fn run_once(&mut self, timeout: Option<Duration>) -> io::Result<Vec<SchedulerEvent>> {
+        // The deadlines are instants of the clocks of the limiters
+        let timeout = match self.next_timer() {
+            Some((deadline, id)) => {
+                let now = self
+                    .entry(id)
+                    .map_or_else(Instant::now, |e| e.limiter.clock.now());
+                poll_timeout([Some(deadline)], timeout, now)
+            }
+            None => timeout,
+        };
+        // Don't wait to remove the streams that have nothing left to write
+        let closing = self.slots.iter().any(|slot| {
+            slot.entry
+                .as_ref()
+                .is_some_and(|entry| entry.read_closed && entry.output.is_empty())
+        });
+        let timeout = if closing {
+            Some(Duration::ZERO)
+        } else {
+            timeout
+        };
+        match self.poll.poll(&mut self.events, timeout) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut ready = Vec::new();
+        for event in self.events.iter() {
+            let index = event.token().0;
+            if let Some(Slot {
+                entry: Some(entry), ..
+            }) = self.slots.get_mut(index)
+            {
+                entry.readable |= event.is_readable() || event.is_read_closed();
+                entry.writable |= event.is_writable() || event.is_write_closed();
+                ready.push(index);
+            }
+        }
+        // The streams whose tokens are ready
+        while let Some((deadline, id)) = self.next_timer() {
+            let Some(entry) = self.entry_mut(id) else {
+                break;
+            };
+            if deadline > entry.limiter.clock.now() {
+                break;
+            }
+            entry.deadline = None;
+            self.timers.pop();
+            ready.push(id.index);
+        }
+        // Also try the streams with new data to write, and the ones to remove
+        for (index, slot) in self.slots.iter().enumerate() {
+            if let Some(entry) = &slot.entry {
+                let to_write =
+                    entry.writable && !entry.output.is_empty() && entry.deadline.is_none();
+                if to_write || entry.read_closed && entry.output.is_empty() {
+                    ready.push(index);
+                }
+            }
+        }
+        ready.sort_unstable();
+        ready.dedup();
+
+        let mut events = Vec::new();
+        for index in ready {
+            self.drive(index, &mut events);
+        }
+        Ok(events)
+    }
+
+    /// Read and write a stream as much as its socket and its limit allow it
+    fn drive(&mut self, index: usize, events: &mut Vec<SchedulerEvent>) {
+        let Some(Slot {
+            generation,
+            entry: Some(entry),
+        }) = self.slots.get_mut(index)
+        else {
+            return;
+        };
+        let id = StreamId {
+            index,
+            generation: *generation,
+        };
+        let mut throttled = false;
+        // Whether the end of the stream was read during this run
+        let mut read_end = false;
+
+        let mut buf = [0u8; READ_BUF_SIZE];
+        while entry.readable && !entry.read_closed {
+            match entry.limiter.read(&mut buf) {
+                // Keep the stream until the data queued on it is written
+                Ok(0) => {
+                    entry.read_closed = true;
+                    entry.readable = false;
+                    read_end = true;
+                }
+                Ok(n) => events.push(SchedulerEvent::Data(id, buf[..n].to_vec())),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // Throttled by the limiter, the socket may still have data
+                    if entry.limiter.ready_at.0.is_some() {
+                        throttled = true;
+                    } else {
+                        entry.readable = false;
+                    }
+                    break;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    events.push(SchedulerEvent::Error(id, e));
+                    self.remove(id);
+                    return;
+                }
+            }
+        }
+
+        while entry.writable && !entry.output.is_empty() {
+            match entry.limiter.write(&entry.output) {
+                Ok(0) => {
+                    events.push(SchedulerEvent::Error(
+                        id,
+                        io::Error::new(io::ErrorKind::WriteZero, "failed to write queued data"),
+                    ));
+                    self.remove(id);
+                    return;
+                }
+                Ok(n) => {
+                    entry.output.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if entry.limiter.ready_at.1.is_some() {
+                        throttled = true;
+                    } else {
+                        entry.writable = false;
+                    }
+                    break;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    events.push(SchedulerEvent::Error(id, e));
+                    self.remove(id);
+                    return;
+                }
+            }
+        }
+
+        // The data sent in answer to the last data read is queued before the next run
+        if entry.read_closed && entry.output.is_empty() && !read_end {
+            events.push(SchedulerEvent::Closed(id));
+            self.remove(id);
+            return;
+        }
+
+        // Wake up once the throttled operations get their tokens
+        entry.deadline = if throttled {
+            entry.limiter.next_ready_at()
+        } else {
+            None
+        };
+        if let Some(deadline) = entry.deadline {
+            self.timers.push(Reverse((deadline, id)));
+        }
+    }
+
+    /// Get the earliest deadline still awaited, dropping the ones of the streams removed
+    /// or driven since
+    fn next_timer(&mut self) -> Option<(Instant, StreamId)> {
+        while let Some(Reverse((deadline, id))) = self.timers.peek().copied() {
+            if self
+                .entry(id)
+                .is_some_and(|entry| entry.deadline == Some(deadline))
+            {
+                return Some((deadline, id));
+            }
+            self.timers.pop();
+        }
+        None
+    }
+
+    fn entry(&self, id: StreamId) -> Option<&Entry<S, A>> {
+        self.slots
+            .get(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.entry.as_ref())
+    }
+
+    fn entry_mut(&mut self, id: StreamId) -> Option<&mut Entry<S, A>> {
+        self.slots
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.entry.as_mut())
+    }
+}
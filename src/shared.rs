@@ -0,0 +1,557 @@
+//! A token bucket that can be shared between several `Limiter`, possibly living
+//! in different threads, so that the sum of their throughput respects a single
+//! `LimiterOptions` configuration.
+//! Several buckets can be chained on a `Limiter` (for example one per peer group and
+//! one for the whole process), every operation is then charged on each level.
+//!
+//! A bucket can also be created as a child of another one, like a class of the Linux
+//! HTB queueing discipline: it is guaranteed its own rate, and may borrow the unused
+//! bandwidth of its parent up to a ceiling.
+//!
+//! Limiters waiting on the same bucket are served in turn, by start-time fair queueing:
+//! each request gets a virtual finish time depending on its size and on the weight of
+//! its limiter, the smallest one is served first, and ties are served in arrival order.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, Signal, SystemClock};
+use crate::{LimiterMode, LimiterOptions};
+
+/// State of a token bucket, protected by the mutex of its owner
+#[derive(Debug)]
+struct BucketState {
+    /// Instant at which the tokens were last computed
+    last_check: Instant,
+    /// Tokens left in the bucket at `last_check`
+    tokens: u64,
+    /// Tokens spent in advance, to be paid back by the refill before any new token
+    /// is available (a HTB class keeps being charged when it borrows)
+    debt: u64,
+}
+
+impl BucketState {
+    fn new(now: Instant) -> BucketState {
+        BucketState {
+            last_check: now,
+            tokens: 0,
+            debt: 0,
+        }
+    }
+
+    /// Add the tokens generated since the last check to the bucket.
+    /// Only the time that produced whole tokens is consumed, the remainder is kept
+    /// for the next refill so no token is lost by the integer division.
+    fn refill(&mut self, opts: &LimiterOptions, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_check).as_nanos();
+        let Some(refill) =
+            (elapsed * u128::from(opts.window_length)).checked_div(u128::from(opts.wtime_ns))
+        else {
+            // If we don't wait at all because of options, the bucket is never empty
+            self.tokens = u64::MAX;
+            self.debt = 0;
+            self.last_check = now;
+            return;
+        };
+        // Pay the debt first
+        let paid = refill.min(u128::from(self.debt));
+        self.debt -= paid as u64;
+        let tokens = u128::from(self.tokens) + refill - paid;
+        // The bucket never holds more than bucket_size tokens, even with the ones given back
+        if tokens >= u128::from(opts.bucket_size) {
+            self.tokens = opts.bucket_size;
+            self.last_check = now;
+        } else {
+            let used_ns = refill * u128::from(opts.wtime_ns) / u128::from(opts.window_length);
+            self.tokens = tokens as u64;
+            self.last_check += Duration::from_nanos(used_ns as u64);
+        }
+    }
+
+    /// Time to wait before the bucket holds `nb` tokens, the bucket has to be refilled first
+    fn wait_time(&self, opts: &LimiterOptions, nb: u64, now: Instant) -> Duration {
+        let missing = u128::from(nb.saturating_add(self.debt).saturating_sub(self.tokens));
+        if missing == 0 {
+            return Duration::ZERO;
+        }
+        let needed_ns =
+            (missing * u128::from(opts.wtime_ns)).div_ceil(u128::from(opts.window_length));
+        let needed = Duration::from_nanos(u64::try_from(needed_ns).unwrap_or(u64::MAX));
+        (self.last_check + needed).saturating_duration_since(now)
+    }
+
+    /// Spend `nb` tokens, even if the bucket doesn't hold them. The missing tokens
+    /// are added to the debt, which never exceeds the size of the bucket.
+    fn force_take(&mut self, opts: &LimiterOptions, nb: u64) {
+        let taken = self.tokens.min(nb);
+        self.tokens -= taken;
+        self.debt = self.debt.saturating_add(nb - taken).min(opts.bucket_size);
+    }
+
+    /// Give back `nb` tokens to the bucket, the debt is paid first
+    fn give_back(&mut self, opts: &LimiterOptions, nb: u64) {
+        let paid = self.debt.min(nb);
+        self.debt -= paid;
+        self.tokens = self.tokens.saturating_add(nb - paid).min(opts.bucket_size);
+    }
+}
+
+/// Check that the options only use what a shared bucket applies
+fn assert_supported(opts: &LimiterOptions) {
+    assert!(
+        matches!(opts.mode, LimiterMode::TokenBucket | LimiterMode::Gcra),
+        "Mode not supported by a shared bucket: {:?}",
+        opts.mode
+    );
+    assert!(
+        opts.peak.is_none(),
+        "Peak rate not supported by a shared bucket"
+    );
+    assert!(
+        opts.iops.is_none(),
+        "IOPS limit not supported by a shared bucket"
+    );
+}
+
+/// Fair queueing state of a bucket
+#[derive(Debug, Default)]
+struct WaitQueue {
+    /// Virtual time of the bucket, start tag of the last request served
+    virtual_time: u128,
+    /// Requests waiting for their turn, by (finish tag, arrival number),
+    /// with the number of tokens they need
+    waiting: BTreeMap<(u128, u64), u64>,
+    /// Arrival number of the next request
+    next_seq: u64,
+}
+
+/// Place of a request in the wait queue of a bucket
+#[derive(Clone, Copy, Debug)]
+struct Ticket {
+    start: u128,
+    finish: u128,
+    seq: u64,
+}
+
+impl Ticket {
+    fn key(&self) -> (u128, u64) {
+        (self.finish, self.seq)
+    }
+}
+
+/// Virtual time taken by a request of `nb` tokens, the heavier the limiter,
+/// the sooner its request finishes
+fn cost(nb: u64, weight: u64) -> u128 {
+    (u128::from(nb.max(1)) << 32) / u128::from(weight.max(1))
+}
+
+/// Fair queueing state of a limiter on a shared bucket
+#[derive(Debug, Default)]
+struct Flow {
+    /// Finish tag of the last request of the limiter served by the bucket
+    finish: u128,
+    /// Request of the limiter waiting in the queue of the bucket
+    ticket: Option<Ticket>,
+    /// Last request served, with the finish tag before it, put back in the queue
+    /// if another level of the chain can't give its tokens
+    served: Option<(Ticket, u128)>,
+}
+
+/// A shared bucket in the chain of a `Limiter`
+pub(crate) struct SharedLink {
+    pub(crate) bucket: Arc<SharedBucket>,
+    flow: Flow,
+}
+
+impl SharedLink {
+    pub(crate) fn new(bucket: Arc<SharedBucket>) -> SharedLink {
+        SharedLink {
+            bucket,
+            flow: Flow::default(),
+        }
+    }
+}
+
+impl Drop for SharedLink {
+    /// Never leave a request in the queue, the limiters behind it would wait forever
+    fn drop(&mut self) {
+        self.bucket.cancel(&mut self.flow);
+    }
+}
+
+/// Borrowing configuration of a child bucket
+struct Borrow {
+    /// Bucket from which the bandwidth is borrowed
+    parent: Arc<SharedBucket>,
+    /// Maximal rate of the child, borrowed bandwidth included
+    ceil: LimiterOptions,
+    ceil_state: Mutex<BucketState>,
+}
+
+/// A token bucket shared between several `Limiter`.
+/// Tokens are taken before each I/O operation, and the ones that weren't used
+/// are given back afterwards, so concurrent limiters never spend the same tokens.
+pub struct SharedBucket {
+    opts: LimiterOptions,
+    state: Mutex<BucketState>,
+    /// Limiters waiting for the tokens of this bucket, always locked before the state
+    queue: Mutex<WaitQueue>,
+    /// Set if this bucket is a child of another one
+    borrow: Option<Borrow>,
+    /// Notified each time tokens are given back to the bucket (or to its family)
+    signal: Arc<Signal>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SharedBucket {
+    /// Create a new empty shared bucket, limited by the given options.
+    /// The bucket only follows the rate and size of the options: the leaky bucket and
+    /// sliding window modes, the peak rate and the IOPS are only applied by the
+    /// algorithm of a `Limiter`, setting any of them panics.
+    pub fn new(opts: LimiterOptions) -> SharedBucket {
+        SharedBucket::with_clock(opts, Arc::new(SystemClock))
+    }
+
+    /// Create a new empty shared bucket using the given clock
+    pub fn with_clock(opts: LimiterOptions, clock: Arc<dyn Clock>) -> SharedBucket {
+        assert_supported(&opts);
+        SharedBucket {
+            state: Mutex::new(BucketState::new(clock.now())),
+            opts,
+            queue: Mutex::new(WaitQueue::default()),
+            borrow: None,
+            signal: Arc::new(Signal::new()),
+            clock,
+        }
+    }
+
+    /// Create a new empty bucket, child of `parent`, like a class of the Linux HTB.
+    /// The child is guaranteed the `rate` options, even if its siblings are busy,
+    /// as long as the sum of the rates of the children doesn't exceed the parent's.
+    /// When it runs out of tokens, it can borrow the idle bandwidth of its parent,
+    /// without ever going faster than the `ceil` options.
+    /// Every operation is charged on the child, its ceiling and its parent.
+    pub fn child(
+        parent: &Arc<SharedBucket>,
+        rate: LimiterOptions,
+        ceil: LimiterOptions,
+    ) -> SharedBucket {
+        assert_supported(&rate);
+        assert_supported(&ceil);
+        let clock = parent.clock.clone();
+        let now = clock.now();
+        SharedBucket {
+            state: Mutex::new(BucketState::new(now)),
+            opts: rate,
+            queue: Mutex::new(WaitQueue::default()),
+            borrow: Some(Borrow {
+                parent: parent.clone(),
+                ceil,
+                ceil_state: Mutex::new(BucketState::new(now)),
+            }),
+            signal: parent.signal.clone(),
+            clock,
+        }
+    }
+
+    /// Get the options limiting this bucket (the guaranteed rate for a child bucket)
+    pub fn options(&self) -> &LimiterOptions {
+        &self.opts
+    }
+
+    /// Get the options of the ceiling of a child bucket
+    pub fn ceil_options(&self) -> Option<&LimiterOptions> {
+        self.borrow.as_ref().map(|borrow| &borrow.ceil)
+    }
+
+    /// Get the parent of a child bucket
+    pub fn parent(&self) -> Option<&Arc<SharedBucket>> {
+        self.borrow.as_ref().map(|borrow| &borrow.parent)
+    }
+
+    /// Get the clock used by this bucket
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Get the number of tokens that can be taken from the bucket right now
+    /// For a child bucket, tokens that can be borrowed from the parent are included
+    pub fn tokens_available(&self) -> u64 {
+        let mut state = self.state.lock().expect("Shared bucket lock poisoned");
+        let now = self.clock.now();
+        state.refill(&self.opts, now);
+        let own = state.tokens;
+        match self.borrow.as_ref() {
+            Some(borrow) => {
+                let mut ceil_state = borrow.ceil_state.lock().expect("Ceil lock poisoned");
+                ceil_state.refill(&borrow.ceil, now);
+                own.max(ceil_state.tokens.min(borrow.parent.tokens_available()))
+            }
+            None => own,
+        }
+    }
+
+    /// Maximal number of tokens that can be taken at once from this bucket
+    pub(crate) fn capacity(&self) -> u64 {
+        match self.borrow.as_ref() {
+            Some(borrow) => self
+                .opts
+                .bucket_size
+                .max(borrow.ceil.bucket_size.min(borrow.parent.capacity())),
+            None => self.opts.bucket_size,
+        }
+    }
+
+    /// Take between `min` and `max` tokens from the bucket.
+    /// If less than `min` tokens are available, returns the time to wait before
+    /// retrying, and the generation of the signal to wait on.
+    pub(crate) fn try_take(&self, min: u64, max: u64) -> Result<u64, (Duration, u64)> {
+        // Read the generation first, so we don't miss any tokens given back meanwhile
+        let generation = self.signal.generation();
+        let mut state = self.state.lock().expect("Shared bucket lock poisoned");
+        let now = self.clock.now();
+        state.refill(&self.opts, now);
+        let Some(borrow) = self.borrow.as_ref() else {
+            if state.tokens < min {
+                return Err((state.wait_time(&self.opts, min, now), generation));
+            }
+            let taken = state.tokens.min(max);
+            state.tokens -= taken;
+            return Ok(taken);
+        };
+
+        let mut ceil_state = borrow.ceil_state.lock().expect("Ceil lock poisoned");
+        ceil_state.refill(&borrow.ceil, now);
+        // Guaranteed rate, the parent is charged even if it doesn't have the tokens
+        if state.tokens >= min {
+            let taken = state.tokens.min(max);
+            state.tokens -= taken;
+            ceil_state.force_take(&borrow.ceil, taken);
+            borrow.parent.force_take(taken);
+            return Ok(taken);
+        }
+        // Borrow from the parent, up to the ceiling
+        let own_wait = state.wait_time(&self.opts, min, now);
+        if ceil_state.tokens < min {
+            let ceil_wait = ceil_state.wait_time(&borrow.ceil, min, now);
+            let parent_wait = borrow.parent.time_until(min).0;
+            return Err((own_wait.min(ceil_wait.max(parent_wait)), generation));
+        }
+        match borrow.parent.try_take(min, ceil_state.tokens.min(max)) {
+            Ok(taken) => {
+                ceil_state.tokens -= taken;
+                state.force_take(&self.opts, taken);
+                Ok(taken)
+            }
+            Err((parent_wait, _)) => Err((own_wait.min(parent_wait), generation)),
+        }
+    }
+
+    /// Take between `min` and `max` tokens from the bucket, waiting for our turn in the
+    /// queue of the bucket. The limiter keeps its place in the queue between two calls,
+    /// until it is served or until the request is cancelled.
+    fn acquire(
+        &self,
+        min: u64,
+        max: u64,
+        weight: u64,
+        flow: &mut Flow,
+    ) -> Result<u64, (Duration, u64)> {
+        let generation = self.signal.generation();
+        let mut queue = self.queue.lock().expect("Shared queue lock poisoned");
+        let ticket = match flow.ticket {
+            Some(ticket) => ticket,
+            None => {
+                let start = queue.virtual_time.max(flow.finish);
+                let ticket = Ticket {
+                    start,
+                    finish: start.saturating_add(cost(min, weight)),
+                    seq: queue.next_seq,
+                };
+                queue.next_seq = queue.next_seq.wrapping_add(1);
+                queue.waiting.insert(ticket.key(), min);
+                flow.ticket = Some(ticket);
+                ticket
+            }
+        };
+
+        // Only the first request of the queue can take tokens, the others wait
+        // for the tokens of every request before them
+        let head = queue.waiting.keys().next().copied();
+        if head != Some(ticket.key()) {
+            let ahead = queue
+                .waiting
+                .range(..ticket.key())
+                .fold(min, |total, (_, nb)| total.saturating_add(*nb));
+            let tsleep = self.time_until(ahead).0;
+            return Err((tsleep, generation));
+        }
+
+        let taken = self.try_take(min, max)?;
+        queue.waiting.remove(&ticket.key());
+        queue.virtual_time = ticket.start;
+        flow.served = Some((ticket, flow.finish));
+        // Charge the tokens actually taken, the request may get more than its minimum
+        flow.finish = ticket.start.saturating_add(cost(taken, weight));
+        flow.ticket = None;
+        let others_waiting = !queue.waiting.is_empty();
+        drop(queue);
+        // Let the next request of the queue try to take its tokens
+        if others_waiting {
+            self.signal.notify();
+        }
+        Ok(taken)
+    }
+
+    /// Put the last request served back in the queue, its tokens were given back
+    /// because another level of the chain couldn't give them. It keeps its place, but
+    /// never goes before the head of the queue: the limiter is waiting on another level,
+    /// and the head could itself be waiting for us there
+    fn requeue(&self, min: u64, flow: &mut Flow) {
+        if let Some((mut ticket, finish)) = flow.served.take() {
+            let mut queue = self.queue.lock().expect("Shared queue lock poisoned");
+            if let Some(&(head_finish, _)) = queue.waiting.keys().next() {
+                if head_finish >= ticket.finish {
+                    ticket.finish = head_finish;
+                    ticket.seq = queue.next_seq;
+                    queue.next_seq = queue.next_seq.wrapping_add(1);
+                }
+            }
+            queue.waiting.insert(ticket.key(), min);
+            flow.finish = finish;
+            flow.ticket = Some(ticket);
+        }
+    }
+
+    /// Remove the request of a limiter from the queue of the bucket
+    fn cancel(&self, flow: &mut Flow) {
+        if let Some(ticket) = flow.ticket.take() {
+            let mut queue = self.queue.lock().expect("Shared queue lock poisoned");
+            queue.waiting.remove(&ticket.key());
+            drop(queue);
+            self.signal.notify();
+        }
+    }
+
+    /// Spend tokens even if the bucket doesn't hold them, the missing ones are
+    /// added to the debt of the bucket
+    fn force_take(&self, nb: u64) {
+        let mut state = self.state.lock().expect("Shared bucket lock poisoned");
+        let now = self.clock.now();
+        state.refill(&self.opts, now);
+        state.force_take(&self.opts, nb);
+        if let Some(borrow) = self.borrow.as_ref() {
+            let mut ceil_state = borrow.ceil_state.lock().expect("Ceil lock poisoned");
+            ceil_state.refill(&borrow.ceil, now);
+            ceil_state.force_take(&borrow.ceil, nb);
+            borrow.parent.force_take(nb);
+        }
+    }
+
+    /// Get the time to wait before `nb` tokens are available in the bucket,
+    /// and the generation of the signal to wait on
+    pub(crate) fn time_until(&self, nb: u64) -> (Duration, u64) {
+        let generation = self.signal.generation();
+        let mut state = self.state.lock().expect("Shared bucket lock poisoned");
+        let now = self.clock.now();
+        state.refill(&self.opts, now);
+        let own_wait = state.wait_time(&self.opts, nb, now);
+        match self.borrow.as_ref() {
+            Some(borrow) => {
+                let mut ceil_state = borrow.ceil_state.lock().expect("Ceil lock poisoned");
+                ceil_state.refill(&borrow.ceil, now);
+                let ceil_wait = ceil_state.wait_time(&borrow.ceil, nb, now);
+                let parent_wait = borrow.parent.time_until(nb).0;
+                (own_wait.min(ceil_wait.max(parent_wait)), generation)
+            }
+            None => (own_wait, generation),
+        }
+    }
+
+    /// Give back tokens taken but not used, wakes up the limiters waiting for them
+    pub(crate) fn refund(&self, nb: u64) {
+        if nb == 0 {
+            return;
+        }
+        {
+            let mut state = self.state.lock().expect("Shared bucket lock poisoned");
+            let now = self.clock.now();
+            state.refill(&self.opts, now);
+            state.give_back(&self.opts, nb);
+            if let Some(borrow) = self.borrow.as_ref() {
+                let mut ceil_state = borrow.ceil_state.lock().expect("Ceil lock poisoned");
+                ceil_state.refill(&borrow.ceil, now);
+                ceil_state.give_back(&borrow.ceil, nb);
+                borrow.parent.refund(nb);
+            }
+        }
+        self.signal.notify();
+    }
+
+    /// Get the signal notified when tokens are given back to the bucket
+    pub(crate) fn signal(&self) -> &Arc<Signal> {
+        &self.signal
+    }
+}
+
+/// Take between `min` and `max` tokens from every bucket of a chain (for example the
+/// buckets of a peer group and of the whole process). Either all the buckets give the
+/// same number of tokens, or none of them is charged.
+/// The limiter keeps its place in the queues of the levels that were refunded, behind
+/// their head.
+/// If a bucket doesn't have enough tokens, or if other limiters are before us in its
+/// queue, returns the index of the most restrictive bucket of the chain, the time to
+/// wait for it and the generation of its signal.
+pub(crate) fn take_chain(
+    links: &mut [SharedLink],
+    weight: u64,
+    min: u64,
+    max: u64,
+) -> Result<u64, (usize, Duration, u64)> {
+    // Never ask for more than what the smallest bucket can hold
+    let min = links
+        .iter()
+        .fold(min, |min, link| min.min(link.bucket.capacity()));
+    // All the buckets before the current one are charged of exactly `nb` tokens
+    let mut nb = max;
+    for idx in 0..links.len() {
+        let (charged, rest) = links.split_at_mut(idx);
+        let link = &mut rest[0];
+        match link.bucket.acquire(min, nb, weight, &mut link.flow) {
+            Ok(taken) => {
+                refund_chain(charged, nb - taken);
+                nb = taken;
+            }
+            Err((tsleep, generation)) => {
+                refund_chain(charged, nb);
+                for link in charged.iter_mut() {
+                    link.bucket.requeue(min, &mut link.flow);
+                }
+                // Wait for the level that needs the most time to get the tokens
+                let mut wait = (idx, tsleep, generation);
+                for (next_idx, next) in links.iter().enumerate().skip(idx + 1) {
+                    let (tsleep, generation) = next.bucket.time_until(min);
+                    if tsleep > wait.1 {
+                        wait = (next_idx, tsleep, generation);
+                    }
+                }
+                return Err(wait);
+            }
+        }
+    }
+    Ok(nb)
+}
+
+/// Give back tokens to every bucket of a chain
+pub(crate) fn refund_chain(links: &[SharedLink], nb: u64) {
+    for link in links {
+        link.bucket.refund(nb);
+    }
+}
+
+/// Remove the requests of a limiter from the queues of every bucket of a chain
+pub(crate) fn cancel_chain(links: &mut [SharedLink]) {
+    for link in links {
+        link.bucket.cancel(&mut link.flow);
+    }
+}
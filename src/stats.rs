@@ -0,0 +1,93 @@
+//! Statistics gathered by a `Limiter` on each direction, always enabled.
+//! They are kept in atomics shared with the `LimiterHandle`s, so they can be read
+//! while another thread is blocked in a read or a write.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Snapshot of the statistics of one direction (read or write) of a `Limiter`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LimiterStats {
+    /// Number of bytes transferred
+    pub bytes: u64,
+    /// Time spent in the operations of the inner stream
+    pub io_time: Duration,
+    /// Time spent waiting for tokens (or while paused)
+    pub sleep_time: Duration,
+    /// Number of calls that had to wait for tokens at least once
+    pub throttled: u64,
+    /// Number of calls that had to wait on the operations limit (IOPS) at least once
+    pub ops_throttled: u64,
+    /// Number of calls to read / write
+    pub calls: u64,
+    /// Number of calls that timed out
+    pub timeouts: u64,
+}
+
+/// Statistics of one direction, updated by the limiter
+#[derive(Default)]
+pub(crate) struct AtomicStats {
+    bytes: AtomicU64,
+    io_nanos: AtomicU64,
+    sleep_nanos: AtomicU64,
+    throttled: AtomicU64,
+    ops_throttled: AtomicU64,
+    calls: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+/// Saturate a duration to a number of nanoseconds (about 584 years)
+fn as_nanos(dur: Duration) -> u64 {
+    u64::try_from(dur.as_nanos()).unwrap_or(u64::MAX)
+}
+
+impl AtomicStats {
+    pub(crate) fn snapshot(&self) -> LimiterStats {
+        LimiterStats {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            io_time: Duration::from_nanos(self.io_nanos.load(Ordering::Relaxed)),
+            sleep_time: Duration::from_nanos(self.sleep_nanos.load(Ordering::Relaxed)),
+            throttled: self.throttled.load(Ordering::Relaxed),
+            ops_throttled: self.ops_throttled.load(Ordering::Relaxed),
+            calls: self.calls.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Count an operation on the inner stream
+    pub(crate) fn add_io(&self, nb: u64, dur: Duration) {
+        self.bytes.fetch_add(nb, Ordering::Relaxed);
+        self.io_nanos.fetch_add(as_nanos(dur), Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_sleep(&self, dur: Duration) {
+        self.sleep_nanos.fetch_add(as_nanos(dur), Ordering::Relaxed);
+    }
+
+    /// Count the call as throttled, once per call
+    pub(crate) fn add_throttled(&self, already_throttled: &mut bool) {
+        if !*already_throttled {
+            *already_throttled = true;
+            self.throttled.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count the call as throttled by the operations limit, once per call
+    pub(crate) fn add_ops_throttled(&self, already_throttled: &mut bool) {
+        if !*already_throttled {
+            *already_throttled = true;
+            self.ops_throttled.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn add_call(&self) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_timeout(&self) {
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,104 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{Clock, Limiter, LimiterOptions, ManualClock, RateAlgorithm};
+
+/// Allows `window_length` bytes in each fixed window of `window_time`
+struct FixedWindow {
+    opts: LimiterOptions,
+    window_start: Instant,
+    used: u64,
+}
+
+impl FixedWindow {
+    fn new(opts: LimiterOptions, now: Instant) -> FixedWindow {
+        FixedWindow {
+            opts,
+            window_start: now,
+            used: 0,
+        }
+    }
+
+    /// Get the start of the window containing `now`, and the bytes used in it
+    fn window(&self, now: Instant) -> (Instant, u64) {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        let windows = (elapsed.as_nanos() / self.opts.window_time.as_nanos()) as u32;
+        if windows == 0 {
+            (self.window_start, self.used)
+        } else {
+            (self.window_start + self.opts.window_time * windows, 0)
+        }
+    }
+}
+
+impl RateAlgorithm for FixedWindow {
+    fn options(&self) -> &LimiterOptions {
+        &self.opts
+    }
+
+    fn set_options(&mut self, opts: LimiterOptions, now: Instant) {
+        *self = FixedWindow::new(opts, now);
+    }
+
+    fn available(&self, now: Instant) -> u64 {
+        self.opts.window_length - self.window(now).1
+    }
+
+    fn time_until(&self, nb: u64, now: Instant) -> Duration {
+        if self.available(now) >= nb {
+            Duration::ZERO
+        } else {
+            (self.window(now).0 + self.opts.window_time).saturating_duration_since(now)
+        }
+    }
+
+    fn consume(&mut self, used: u64, start: Instant, _now: Instant) {
+        let (window_start, window_used) = self.window(start);
+        self.window_start = window_start;
+        self.used = window_used + used;
+    }
+}
+
+#[test]
+fn custom_algorithm() {
+    let clock = Arc::new(ManualClock::new());
+    let opts = LimiterOptions::new(10, Duration::from_secs(1), 10);
+    let mut limiter = Limiter::with_algorithm(
+        std::io::Cursor::new(Vec::new()),
+        None,
+        Some(FixedWindow::new(opts, clock.now())),
+        clock.clone(),
+    );
+    // The first window is available right away, then one window per second
+    assert_eq!(limiter.write(&[0u8; 25]).unwrap(), 25);
+    assert_eq!(clock.elapsed(), Duration::from_secs(2));
+    clock.sleep(Duration::from_millis(500));
+    // Half of the current window is left
+    assert_eq!(limiter.write(&[0u8; 5]).unwrap(), 5);
+    assert_eq!(clock.elapsed(), Duration::from_millis(2500));
+    assert_eq!(limiter.write(&[0u8; 10]).unwrap(), 10);
+    assert_eq!(clock.elapsed(), Duration::from_secs(3));
+    assert_eq!(limiter.get_stream().into_inner().len(), 40);
+}
+
+#[test]
+fn custom_algorithm_keeps_its_state() {
+    let clock = Arc::new(ManualClock::new());
+    let opts = LimiterOptions::new(10, Duration::from_secs(1), 10);
+    // The current window was already used by something else
+    let mut algorithm = FixedWindow::new(opts.clone(), clock.now());
+    algorithm.consume(10, clock.now(), clock.now());
+    let mut limiter = Limiter::with_algorithm(
+        std::io::Cursor::new(Vec::new()),
+        None,
+        Some(algorithm),
+        clock.clone(),
+    );
+    assert_eq!(limiter.write(&[0u8; 10]).unwrap(), 10);
+    assert_eq!(clock.elapsed(), Duration::from_secs(1));
+
+    // Without `from_options`, options given to the reads leave them unlimited
+    limiter.set_read_options(Some(opts));
+    assert!(limiter.read_options().is_none());
+}
@@ -0,0 +1,155 @@
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{
+    CancelToken, Cancelled, Direction, Limiter, LimiterOptions, ManualClock, SharedBucket,
+};
+
+/// Stream cancelling the token on its first write
+struct CancelOnWrite {
+    token: CancelToken,
+    data: Vec<u8>,
+}
+
+impl Read for CancelOnWrite {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for CancelOnWrite {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.token.cancel();
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Check that the error comes from the cancellation, not from the stream
+fn assert_cancelled(err: std::io::Error, dir: Direction) {
+    assert_eq!(err.kind(), ErrorKind::ConnectionAborted);
+    let inner = err.get_ref().unwrap();
+    assert_eq!(inner.downcast_ref::<Cancelled>(), Some(&Cancelled(dir)));
+}
+
+#[test]
+fn cancel_returns_bytes_written_so_far() {
+    let clock = Arc::new(ManualClock::new());
+    let token = CancelToken::new();
+    let stream = CancelOnWrite {
+        token: token.clone(),
+        data: vec![],
+    };
+    let mut limiter = Limiter::with_clock(
+        stream,
+        None,
+        Some(LimiterOptions::new(5, Duration::from_secs(1), 5)),
+        clock.clone(),
+    );
+    limiter.set_cancel_token(token);
+    assert_eq!(limiter.write(&[1u8; 20]).unwrap(), 5);
+    assert_eq!(clock.elapsed(), Duration::from_secs(1));
+
+    // Nothing is transferred after the cancellation
+    assert_cancelled(limiter.write(&[1u8; 20]).unwrap_err(), Direction::Write);
+    assert_eq!(clock.elapsed(), Duration::from_secs(1));
+    assert_eq!(limiter.stream.data.len(), 5);
+}
+
+#[test]
+fn cancel_stops_write_all() {
+    let clock = Arc::new(ManualClock::new());
+    let token = CancelToken::new();
+    let stream = CancelOnWrite {
+        token: token.clone(),
+        data: vec![],
+    };
+    let mut limiter = Limiter::with_clock(
+        stream,
+        None,
+        Some(LimiterOptions::new(5, Duration::from_secs(1), 5)),
+        clock.clone(),
+    );
+    limiter.set_cancel_token(token);
+    assert_cancelled(limiter.write_all(&[1u8; 20]).unwrap_err(), Direction::Write);
+    assert_eq!(clock.elapsed(), Duration::from_secs(1));
+    assert_eq!(limiter.stream.data.len(), 5);
+}
+
+#[test]
+fn cancel_wakes_sleeping_limiters() {
+    let token = CancelToken::new();
+    let bucket = Arc::new(SharedBucket::new(LimiterOptions::new(
+        1,
+        Duration::from_secs(10),
+        1,
+    )));
+    let mut writers = vec![];
+    for _ in 0..3 {
+        let mut limiter =
+            Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(bucket.clone()));
+        limiter.set_cancel_token(token.clone());
+        writers.push(std::thread::spawn(move || limiter.write(&[1u8; 10])));
+    }
+    let mut limiter = Limiter::new(
+        std::io::Cursor::new(vec![0u8; 10]),
+        Some(LimiterOptions::new(1, Duration::from_secs(10), 1)),
+        None,
+    );
+    limiter.set_cancel_token(token.clone());
+    let reader = std::thread::spawn(move || limiter.read(&mut [0u8; 10]));
+
+    let start = Instant::now();
+    std::thread::sleep(Duration::from_millis(300));
+    token.cancel();
+    assert!(token.is_cancelled());
+    for writer in writers {
+        assert_cancelled(writer.join().unwrap().unwrap_err(), Direction::Write);
+    }
+    assert_cancelled(reader.join().unwrap().unwrap_err(), Direction::Read);
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn cancel_while_paused() {
+    let token = CancelToken::new();
+    let mut limiter = Limiter::new(std::io::Cursor::new(vec![]), None, None);
+    limiter.set_cancel_token(token.clone());
+    limiter.handle().pause_write();
+    let writer = std::thread::spawn(move || limiter.write(&[1u8; 10]));
+    std::thread::sleep(Duration::from_millis(100));
+    token.cancel();
+    assert_cancelled(writer.join().unwrap().unwrap_err(), Direction::Write);
+}
+
+#[test]
+fn aborted_stream_is_not_cancelled() {
+    /// Stream whose connection was aborted by the peer
+    struct Aborted;
+
+    impl Read for Aborted {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(ErrorKind::ConnectionAborted.into())
+        }
+    }
+
+    impl Write for Aborted {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(ErrorKind::ConnectionAborted.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut limiter = Limiter::new(Aborted, None, None);
+    limiter.set_cancel_token(CancelToken::new());
+    let err = limiter.write(&[1u8; 10]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionAborted);
+    assert!(!err.get_ref().is_some_and(|inner| inner.is::<Cancelled>()));
+}
@@ -0,0 +1,118 @@
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::utils::{assert_checksum, assert_checksum_samedata, open_file, FILE_BIG};
+use crate::{Clock, Limiter, LimiterOptions, ManualClock};
+
+#[test]
+fn manual_clock_advance() {
+    let clock = ManualClock::new();
+    let start = clock.now();
+    clock.advance(Duration::from_secs(3));
+    clock.sleep(Duration::from_millis(500));
+    assert_eq!(clock.now() - start, Duration::from_millis(3500));
+    assert_eq!(clock.elapsed(), Duration::from_millis(3500));
+}
+
+#[test]
+fn virtual_read_one_byte_each_second() {
+    let clock = Arc::new(ManualClock::new());
+    let file = open_file("test.txt");
+    let mut limiter = Limiter::with_clock(
+        file,
+        Some(LimiterOptions::new(1, Duration::from_secs(1), 10)),
+        None,
+        clock.clone(),
+    );
+    let mut buf = [0u8; 10];
+    let now = std::time::Instant::now();
+    assert_eq!(limiter.read(&mut buf).unwrap(), 10);
+    assert_eq!(clock.elapsed(), Duration::from_secs(10));
+    assert!(
+        now.elapsed() < Duration::from_secs(1),
+        "{:?}",
+        now.elapsed()
+    );
+}
+
+#[test]
+fn virtual_write_two_bytes_each_second() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(vec![]),
+        None,
+        Some(LimiterOptions::new(2, Duration::from_secs(1), 10)),
+        clock.clone(),
+    );
+    assert_eq!(limiter.write(&[18u8; 10]).unwrap(), 10);
+    assert_eq!(clock.elapsed(), Duration::from_secs(5));
+    assert_checksum_samedata::<10>(&limiter.stream.into_inner(), 18);
+}
+
+#[test]
+fn virtual_burst() {
+    let clock = Arc::new(ManualClock::new());
+    let file = open_file("big.txt");
+    let mut limiter = Limiter::with_clock(
+        file,
+        Some(LimiterOptions::new(1024, Duration::from_secs(1), 12 * 1024)),
+        None,
+        clock.clone(),
+    );
+
+    // Fill the bucket, the whole file is then read without sleeping
+    clock.advance(Duration::from_secs(12));
+    let mut buf = [0u8; 11 * 1024];
+    assert_eq!(limiter.read(&mut buf).unwrap(), 11 * 1024);
+    assert_eq!(clock.elapsed(), Duration::from_secs(12));
+    assert_checksum(&buf, &FILE_BIG);
+}
+
+#[test]
+fn virtual_timeout() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limopt = LimiterOptions::new(1, Duration::from_secs(1), 10);
+    limopt.set_timeout(Duration::from_millis(2500));
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(vec![]),
+        None,
+        Some(limopt),
+        clock.clone(),
+    );
+    let err = limiter.write(&[0u8; 10]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert_eq!(clock.elapsed(), Duration::from_millis(2500));
+}
+
+/// Virtual clock implementing only `now` and `sleep`, as a user would
+struct SleepOnlyClock(Mutex<Instant>);
+
+impl Clock for SleepOnlyClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+
+    fn sleep(&self, dur: Duration) {
+        *self.0.lock().unwrap() += dur;
+    }
+}
+
+#[test]
+fn default_wait_sleeps_on_the_clock() {
+    let start = Instant::now();
+    let clock = Arc::new(SleepOnlyClock(Mutex::new(start)));
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(vec![]),
+        None,
+        Some(LimiterOptions::new(1, Duration::from_secs(1), 10)),
+        clock.clone(),
+    );
+    assert_eq!(limiter.write(&[3u8; 10]).unwrap(), 10);
+    assert_eq!(clock.now() - start, Duration::from_secs(10));
+    assert!(
+        start.elapsed() < Duration::from_secs(1),
+        "{:?}",
+        start.elapsed()
+    );
+}
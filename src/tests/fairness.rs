@@ -0,0 +1,218 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::shared::{take_chain, SharedLink};
+use crate::{Clock, Limiter, LimiterOptions, ManualClock, SharedBucket};
+
+fn bucket(clock: &Arc<ManualClock>) -> Arc<SharedBucket> {
+    Arc::new(SharedBucket::with_clock(
+        LimiterOptions::new(10, Duration::from_secs(1), 10),
+        clock.clone(),
+    ))
+}
+
+#[test]
+fn tokens_shared_by_weight() {
+    let clock = Arc::new(ManualClock::new());
+    let bucket = bucket(&clock);
+    let mut flows = [
+        (1, SharedLink::new(bucket.clone()), 0),
+        (3, SharedLink::new(bucket.clone()), 0),
+    ];
+
+    // Both flows always want 2 tokens, one token is produced at each step
+    for _ in 0..400 {
+        clock.advance(Duration::from_millis(100));
+        for (weight, link, served) in flows.iter_mut() {
+            if take_chain(std::slice::from_mut(link), *weight, 2, 2).is_ok() {
+                *served += 1;
+            }
+        }
+    }
+    assert_eq!(flows[0].2 + flows[1].2, 200);
+    assert!((49..=51).contains(&flows[0].2), "{}", flows[0].2);
+}
+
+#[test]
+fn large_requests_charged_what_they_take() {
+    let clock = Arc::new(ManualClock::new());
+    let bucket = bucket(&clock);
+    // The first flow takes up to 10 tokens at once, the second one token at a time
+    let mut flows = [
+        (10, SharedLink::new(bucket.clone()), 0),
+        (1, SharedLink::new(bucket.clone()), 0),
+    ];
+
+    // Tokens build up between the steps, each flow takes them until it has to wait
+    for step in 0..400 {
+        clock.advance(Duration::from_millis(500));
+        for i in 0..2 {
+            let (max, link, taken) = &mut flows[(step + i) % 2];
+            while let Ok(nb) = take_chain(std::slice::from_mut(link), 1, 1, *max) {
+                *taken += nb;
+            }
+        }
+    }
+    let (large, small) = (flows[0].2, flows[1].2);
+    assert!(large.abs_diff(small) <= 20, "{large} {small}");
+}
+
+#[test]
+fn waiters_served_in_arrival_order() {
+    let clock = Arc::new(ManualClock::new());
+    let bucket = bucket(&clock);
+    let mut first = SharedLink::new(bucket.clone());
+    let mut second = SharedLink::new(bucket.clone());
+
+    assert!(take_chain(std::slice::from_mut(&mut first), 1, 5, 5).is_err());
+    assert!(take_chain(std::slice::from_mut(&mut second), 1, 5, 5).is_err());
+    clock.advance(Duration::from_millis(500));
+
+    // The tokens are there, but the second waiter can't take them before the first one
+    let (_, tsleep, _) = take_chain(std::slice::from_mut(&mut second), 1, 5, 5).unwrap_err();
+    assert_eq!(tsleep, Duration::from_millis(500));
+    assert_eq!(take_chain(std::slice::from_mut(&mut first), 1, 5, 5), Ok(5));
+    clock.advance(Duration::from_millis(500));
+    assert_eq!(
+        take_chain(std::slice::from_mut(&mut second), 1, 5, 5),
+        Ok(5)
+    );
+}
+
+#[test]
+fn chain_keeps_place_in_refunded_levels() {
+    let clock = Arc::new(ManualClock::new());
+    let group = bucket(&clock);
+    let global = bucket(&clock);
+    let mut chained = [
+        SharedLink::new(group.clone()),
+        SharedLink::new(global.clone()),
+    ];
+    let mut group_only = SharedLink::new(group.clone());
+    let mut global_only = SharedLink::new(global.clone());
+    clock.advance(Duration::from_millis(500));
+
+    // The global level is drained, the group tokens taken by the chain are given back
+    assert_eq!(
+        take_chain(std::slice::from_mut(&mut global_only), 1, 5, 5),
+        Ok(5)
+    );
+    assert!(take_chain(&mut chained, 1, 5, 5).is_err());
+    assert_eq!(group.tokens_available(), 5);
+
+    // The chain is still first in the queue of the group
+    assert!(take_chain(std::slice::from_mut(&mut group_only), 1, 5, 5).is_err());
+    clock.advance(Duration::from_millis(500));
+    assert_eq!(take_chain(&mut chained, 1, 5, 5), Ok(5));
+    assert_eq!(
+        take_chain(std::slice::from_mut(&mut group_only), 1, 5, 5),
+        Ok(5)
+    );
+}
+
+#[test]
+fn chains_dont_block_each_other() {
+    let clock = Arc::new(ManualClock::new());
+    let opts = LimiterOptions::new(100, Duration::from_secs(1), 100);
+    let group = Arc::new(SharedBucket::with_clock(opts.clone(), clock.clone()));
+    let global = Arc::new(SharedBucket::with_clock(opts, clock.clone()));
+    let mut x = [
+        SharedLink::new(group.clone()),
+        SharedLink::new(global.clone()),
+    ];
+    let mut y = [
+        SharedLink::new(group.clone()),
+        SharedLink::new(global.clone()),
+    ];
+    let mut global_only = SharedLink::new(global.clone());
+    clock.advance(Duration::from_secs(1));
+
+    // X waits on the global level
+    assert_eq!(
+        take_chain(std::slice::from_mut(&mut global_only), 1, 60, 60),
+        Ok(60)
+    );
+    assert!(take_chain(&mut x, 1, 100, 100).is_err());
+    // The global level serves a request ahead of X, Y then queues behind X
+    // on the global level, but its weight puts it ahead of X on the group
+    assert_eq!(
+        take_chain(std::slice::from_mut(&mut global_only), 1, 1, 40),
+        Ok(40)
+    );
+    assert!(take_chain(&mut y, 2, 100, 100).is_err());
+
+    // Each of them is served once the buckets are refilled
+    let (mut x_done, mut y_done) = (false, false);
+    for _ in 0..10 {
+        clock.advance(Duration::from_secs(1));
+        x_done = x_done || take_chain(&mut x, 1, 100, 100).is_ok();
+        y_done = y_done || take_chain(&mut y, 2, 100, 100).is_ok();
+    }
+    assert!(x_done && y_done, "{x_done} {y_done}");
+}
+
+#[test]
+fn dropped_waiter_leaves_the_queue() {
+    let clock = Arc::new(ManualClock::new());
+    let bucket = bucket(&clock);
+    let mut first = SharedLink::new(bucket.clone());
+    let mut second = SharedLink::new(bucket.clone());
+
+    assert!(take_chain(std::slice::from_mut(&mut first), 1, 5, 5).is_err());
+    assert!(take_chain(std::slice::from_mut(&mut second), 1, 5, 5).is_err());
+    drop(first);
+    clock.advance(Duration::from_millis(500));
+    assert_eq!(
+        take_chain(std::slice::from_mut(&mut second), 1, 5, 5),
+        Ok(5)
+    );
+}
+
+/// Clock whose sleeps wait for the test to move the time forward, so the threads
+/// sleeping on it compete for the tokens as they would on the wall clock
+struct SteppedClock(ManualClock);
+
+impl Clock for SteppedClock {
+    fn now(&self) -> Instant {
+        self.0.now()
+    }
+
+    fn sleep(&self, dur: Duration) {
+        let wake_at = self.0.now() + dur;
+        while self.0.now() < wake_at {
+            std::thread::yield_now();
+        }
+    }
+}
+
+#[test]
+fn weighted_limiters_threads() {
+    let clock = Arc::new(SteppedClock(ManualClock::new()));
+    let bucket = Arc::new(SharedBucket::with_clock(
+        LimiterOptions::new(100, Duration::from_millis(100), 10),
+        clock.clone(),
+    ));
+    let handles: Vec<_> = [1, 4]
+        .into_iter()
+        .map(|weight| {
+            let bucket = bucket.clone();
+            let clock = clock.clone();
+            std::thread::spawn(move || {
+                let mut limiter =
+                    Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(bucket));
+                limiter.set_write_weight(weight);
+                while clock.0.elapsed() < Duration::from_millis(500) {
+                    limiter.write_all(&[0u8; 10]).unwrap();
+                }
+                limiter.stream.into_inner().len()
+            })
+        })
+        .collect();
+    while !handles.iter().all(|h| h.is_finished()) {
+        clock.0.advance(Duration::from_millis(1));
+        std::thread::sleep(Duration::from_micros(100));
+    }
+    let written: Vec<usize> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    assert!(written[1] > 2 * written[0], "{:?}", written);
+}
@@ -0,0 +1,130 @@
+use std::future::{Future, Ready};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures_lite::future::{block_on, poll_once};
+use futures_lite::{AsyncReadExt, AsyncWriteExt};
+
+use super::utils::assert_checksum_samedata;
+use crate::{Clock, FuturesLimiter, LimiterOptions, ManualClock, Timer};
+
+/// Timer on a virtual clock, moves the time forward instead of waiting
+struct ManualTimer(Arc<ManualClock>);
+
+impl Timer for ManualTimer {
+    type Sleep = Ready<()>;
+
+    fn now(&self) -> Instant {
+        self.0.now()
+    }
+
+    fn sleep(&self, dur: Duration) -> Self::Sleep {
+        self.0.advance(dur);
+        std::future::ready(())
+    }
+}
+
+/// Timer on a virtual clock, its sleeps are pending until the clock is moved forward
+struct SteppedTimer(Arc<ManualClock>);
+
+struct SteppedSleep {
+    clock: Arc<ManualClock>,
+    wake_at: Instant,
+}
+
+impl Future for SteppedSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        if self.clock.now() >= self.wake_at {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Timer for SteppedTimer {
+    type Sleep = SteppedSleep;
+
+    fn now(&self) -> Instant {
+        self.0.now()
+    }
+
+    fn sleep(&self, dur: Duration) -> Self::Sleep {
+        SteppedSleep {
+            clock: self.0.clone(),
+            wake_at: self.0.now() + dur,
+        }
+    }
+}
+
+#[test]
+fn write_one_byte_each_second() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = FuturesLimiter::new(
+        vec![],
+        None,
+        Some(LimiterOptions::new(1, Duration::from_secs(1), 1)),
+        ManualTimer(clock.clone()),
+    );
+    block_on(limiter.write_all(&[42u8; 10])).unwrap();
+    assert_eq!(clock.elapsed(), Duration::from_secs(10));
+    assert_checksum_samedata::<10>(&limiter.stream, 42);
+}
+
+#[test]
+fn read_exact_with_burst() {
+    let clock = Arc::new(ManualClock::new());
+    let data = [7u8; 30];
+    let mut limiter = FuturesLimiter::new(
+        &data[..],
+        Some(LimiterOptions::new(10, Duration::from_secs(1), 20)),
+        None,
+        ManualTimer(clock.clone()),
+    );
+    // The bucket fills up while nothing is read
+    clock.advance(Duration::from_secs(5));
+    let mut buf = [0u8; 30];
+    block_on(limiter.read_exact(&mut buf)).unwrap();
+    assert_eq!(clock.elapsed(), Duration::from_secs(6));
+    assert_checksum_samedata::<30>(&buf, 7);
+}
+
+#[test]
+fn timeout() {
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(1, Duration::from_secs(1), 10);
+    opts.set_min_operation_size(5);
+    opts.set_timeout(Duration::from_millis(2500));
+    let mut limiter = FuturesLimiter::new(vec![], None, Some(opts), ManualTimer(clock.clone()));
+    let err = block_on(limiter.write(&[1u8; 10])).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert_eq!(clock.elapsed(), Duration::from_millis(2500));
+    assert!(limiter.stream.is_empty());
+}
+
+#[test]
+fn dropped_write_doesnt_time_out_the_next() {
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(1, Duration::from_secs(1), 10);
+    opts.set_min_operation_size(5);
+    opts.set_timeout(Duration::from_secs(4));
+    let mut limiter = FuturesLimiter::new(vec![], None, Some(opts), SteppedTimer(clock.clone()));
+    // The write of 2 bytes waits for 2s, its future is dropped
+    assert!(block_on(poll_once(limiter.write(&[1u8; 2]))).is_none());
+    clock.advance(Duration::from_secs(3));
+
+    // The next write waits for 2 more seconds from its own start
+    let mut write = limiter.write(&[1u8; 5]);
+    let written = loop {
+        if let Some(res) = block_on(poll_once(&mut write)) {
+            break res.unwrap();
+        }
+        clock.advance(Duration::from_millis(500));
+    };
+    assert_eq!(written, 5);
+    assert_eq!(clock.elapsed(), Duration::from_secs(5));
+}
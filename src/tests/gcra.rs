@@ -0,0 +1,90 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{Clock, Limiter, LimiterMode, LimiterOptions, ManualClock};
+
+fn gcra(window_length: u64, window_time: Duration, bucket_size: u64) -> LimiterOptions {
+    let mut opts = LimiterOptions::new(window_length, window_time, bucket_size);
+    opts.set_mode(LimiterMode::Gcra);
+    opts
+}
+
+#[test]
+fn no_drift_on_uneven_rate() {
+    let clock = Arc::new(ManualClock::new());
+    // 142857142.857ns per byte, the fractions are not lost between the writes
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(Vec::new()),
+        None,
+        Some(gcra(7, Duration::from_secs(1), 1)),
+        clock.clone(),
+    );
+    for _ in 0..700 {
+        assert_eq!(limiter.write(&[0u8]).unwrap(), 1);
+    }
+    // Each byte is rounded up to the nanosecond at most
+    let elapsed = clock.elapsed();
+    assert!(
+        elapsed >= Duration::from_secs(100)
+            && elapsed <= Duration::from_secs(100) + Duration::from_micros(1),
+        "{elapsed:?}"
+    );
+}
+
+#[test]
+fn burst_then_rate() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(Vec::new()),
+        None,
+        Some(gcra(10, Duration::from_secs(1), 100)),
+        clock.clone(),
+    );
+    // The bucket fills up to its size while idle, then follows the rate
+    clock.sleep(Duration::from_secs(60));
+    assert_eq!(limiter.write(&[0u8; 100]).unwrap(), 100);
+    assert_eq!(clock.elapsed(), Duration::from_secs(60));
+    assert_eq!(limiter.write(&[0u8; 50]).unwrap(), 50);
+    assert_eq!(clock.elapsed(), Duration::from_secs(65));
+}
+
+#[test]
+fn exact_retry_after() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(Vec::new()),
+        None,
+        Some(gcra(3, Duration::from_secs(1), 3)),
+        clock.clone(),
+    );
+    limiter.set_nonblocking(true);
+    let start = clock.now();
+    clock.advance(Duration::from_millis(500));
+    assert_eq!(
+        limiter.write(&[0u8; 3]).unwrap_err().kind(),
+        std::io::ErrorKind::WouldBlock
+    );
+    assert_eq!(
+        limiter.next_ready_at(),
+        Some(start + Duration::from_secs(1))
+    );
+    clock.advance(Duration::from_millis(500));
+    assert_eq!(limiter.write(&[0u8; 3]).unwrap(), 3);
+}
+
+#[test]
+fn keeps_tokens_on_new_options() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(Vec::new()),
+        None,
+        Some(LimiterOptions::new(10, Duration::from_secs(1), 10)),
+        clock.clone(),
+    );
+    clock.sleep(Duration::from_secs(1));
+    // Switching to GCRA keeps the 10 tokens of the token bucket
+    limiter.set_write_options(Some(gcra(10, Duration::from_secs(1), 10)));
+    assert_eq!(limiter.write(&[0u8; 10]).unwrap(), 10);
+    assert_eq!(clock.elapsed(), Duration::from_secs(1));
+}
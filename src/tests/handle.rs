@@ -0,0 +1,114 @@
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::utils::assert_checksum_samedata;
+use crate::{Limiter, LimiterHandle, LimiterOptions, ManualClock};
+
+#[test]
+fn handle_is_send_sync() {
+    fn assert_send_sync<T: Send + Sync + Clone>() {}
+    assert_send_sync::<LimiterHandle>();
+}
+
+#[test]
+fn options_applied_on_next_write() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(vec![]),
+        None,
+        Some(LimiterOptions::new(1, Duration::from_secs(1), 10)),
+        clock.clone(),
+    );
+    let handle = limiter.handle();
+    assert_eq!(limiter.write(&[3u8; 5]).unwrap(), 5);
+    assert_eq!(clock.elapsed(), Duration::from_secs(5));
+
+    handle.set_write_options(Some(LimiterOptions::new(5, Duration::from_secs(1), 10)));
+    assert_eq!(limiter.write(&[3u8; 10]).unwrap(), 10);
+    assert_eq!(clock.elapsed(), Duration::from_secs(7));
+
+    handle.set_write_options(None);
+    assert_eq!(limiter.write(&[3u8; 10]).unwrap(), 10);
+    assert_eq!(clock.elapsed(), Duration::from_secs(7));
+    assert_eq!(limiter.limits(), (false, false));
+    assert_eq!(handle.bytes_written(), 25);
+    assert_checksum_samedata::<25>(&limiter.stream.into_inner(), 3);
+}
+
+#[test]
+fn counters() {
+    let mut limiter = Limiter::new(
+        std::io::Cursor::new(vec![7u8; 20]),
+        Some(LimiterOptions::new(100, Duration::from_millis(10), 100)),
+        None,
+    );
+    let handle = limiter.handle();
+    let mut buf = [0u8; 15];
+    assert_eq!(limiter.read(&mut buf).unwrap(), 15);
+    assert_eq!(limiter.read(&mut buf).unwrap(), 5);
+    assert_eq!(limiter.write(&[1u8; 4]).unwrap(), 4);
+    assert_eq!(handle.bytes_read(), 20);
+    assert_eq!(handle.clone().bytes_written(), 4);
+}
+
+#[test]
+fn rate_raised_while_sleeping() {
+    let mut limiter = Limiter::new(
+        std::io::Cursor::new(vec![]),
+        None,
+        Some(LimiterOptions::new(1, Duration::from_secs(1), 1)),
+    );
+    let handle = limiter.handle();
+    let start = Instant::now();
+    let writer = std::thread::spawn(move || {
+        // Would take 10 seconds at the initial rate
+        assert_eq!(limiter.write(&[9u8; 10]).unwrap(), 10);
+        limiter
+    });
+    std::thread::sleep(Duration::from_millis(300));
+    handle.set_write_options(Some(LimiterOptions::new(1000, Duration::from_secs(1), 10)));
+    let limiter = writer.join().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(handle.bytes_written(), 10);
+    assert_checksum_samedata::<10>(&limiter.stream.into_inner(), 9);
+}
+
+#[test]
+fn pause_and_resume() {
+    let mut limiter = Limiter::new(
+        std::io::Cursor::new(vec![]),
+        None,
+        Some(LimiterOptions::new(1000, Duration::from_secs(1), 1000)),
+    );
+    let handle = limiter.handle();
+    handle.pause_write();
+    assert_eq!(handle.paused(), (false, true));
+    let start = Instant::now();
+    let writer = std::thread::spawn(move || limiter.write(&[1u8; 10]).unwrap());
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(handle.bytes_written(), 0);
+    handle.resume_write();
+    assert_eq!(writer.join().unwrap(), 10);
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(handle.bytes_written(), 10);
+}
+
+#[test]
+fn paused_timeout() {
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(10, Duration::from_secs(1), 10);
+    opts.set_timeout(Duration::from_secs(3));
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(vec![0u8; 10]),
+        Some(opts),
+        None,
+        clock.clone(),
+    );
+    limiter.handle().pause_read();
+    let mut buf = [0u8; 10];
+    let err = limiter.read(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert_eq!(clock.elapsed(), Duration::from_secs(3));
+}
@@ -0,0 +1,77 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{Limiter, LimiterOptions, ManualClock, SharedBucket};
+
+fn htb_family(clock: &Arc<ManualClock>) -> (Arc<SharedBucket>, Arc<SharedBucket>) {
+    let parent = Arc::new(SharedBucket::with_clock(
+        LimiterOptions::new(20, Duration::from_secs(1), 20),
+        clock.clone(),
+    ));
+    let child_a = Arc::new(SharedBucket::child(
+        &parent,
+        LimiterOptions::new(10, Duration::from_secs(1), 10),
+        LimiterOptions::new(20, Duration::from_secs(1), 20),
+    ));
+    let child_b = Arc::new(SharedBucket::child(
+        &parent,
+        LimiterOptions::new(10, Duration::from_secs(1), 10),
+        LimiterOptions::new(20, Duration::from_secs(1), 20),
+    ));
+    (child_a, child_b)
+}
+
+#[test]
+fn borrow_idle_bandwidth() {
+    let clock = Arc::new(ManualClock::new());
+    let (child_a, _child_b) = htb_family(&clock);
+    assert_eq!(child_a.ceil_options().unwrap().window_length, 20);
+    let mut limiter = Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(child_a));
+
+    // The sibling is quiet, 40 bytes at the 20 bytes / sec of the ceiling
+    assert_eq!(limiter.write(&[1u8; 40]).unwrap(), 40);
+    assert_eq!(clock.elapsed(), Duration::from_secs(2));
+}
+
+#[test]
+fn ceil_caps_borrowing() {
+    let clock = Arc::new(ManualClock::new());
+    let parent = Arc::new(SharedBucket::with_clock(
+        LimiterOptions::new(100, Duration::from_secs(1), 100),
+        clock.clone(),
+    ));
+    let child = Arc::new(SharedBucket::child(
+        &parent,
+        LimiterOptions::new(10, Duration::from_secs(1), 10),
+        LimiterOptions::new(20, Duration::from_secs(1), 20),
+    ));
+    let mut limiter = Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(child));
+
+    // The parent has plenty of bandwidth, but the child never exceeds its ceiling
+    assert_eq!(limiter.write(&[1u8; 60]).unwrap(), 60);
+    assert_eq!(clock.elapsed(), Duration::from_secs(3));
+}
+
+#[test]
+fn guaranteed_rate_while_sibling_borrows() {
+    let clock = Arc::new(ManualClock::new());
+    let (child_a, child_b) = htb_family(&clock);
+    let mut limiter_a =
+        Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(child_a.clone()));
+    let mut limiter_b =
+        Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(child_b.clone()));
+
+    // A borrows all the bandwidth of the parent
+    assert_eq!(limiter_a.write(&[1u8; 40]).unwrap(), 40);
+    assert_eq!(clock.elapsed(), Duration::from_secs(2));
+    assert_eq!(child_a.parent().unwrap().tokens_available(), 0);
+
+    // B didn't use its guaranteed rate yet, it writes without waiting
+    assert_eq!(limiter_b.write(&[2u8; 10]).unwrap(), 10);
+    assert_eq!(clock.elapsed(), Duration::from_secs(2));
+
+    // B's traffic was charged to the parent, A has to wait longer to borrow again
+    assert_eq!(limiter_a.write(&[1u8; 10]).unwrap(), 10);
+    assert_eq!(clock.elapsed(), Duration::from_secs(3));
+}
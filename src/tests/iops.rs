@@ -0,0 +1,127 @@
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{Clock, Constraint, Direction, Limiter, LimiterObserver, LimiterOptions, ManualClock};
+
+/// Stream writing at most `chunk` bytes per operation, counting the operations
+struct ChunkedStream {
+    chunk: usize,
+    ops: usize,
+}
+
+impl Read for ChunkedStream {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for ChunkedStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.ops += 1;
+        Ok(buf.len().min(self.chunk))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Records the constraints reported before each sleep
+#[derive(Default)]
+struct Constraints(Mutex<Vec<Constraint>>);
+
+impl LimiterObserver for Constraints {
+    fn on_throttle(&self, dir: Direction, constraint: Constraint) {
+        assert_eq!(dir, Direction::Write);
+        self.0.lock().unwrap().push(constraint);
+    }
+}
+
+fn limiter(
+    clock: &Arc<ManualClock>,
+    chunk: usize,
+    opts: LimiterOptions,
+) -> (Limiter<ChunkedStream>, Arc<Constraints>) {
+    let mut limiter = Limiter::with_clock(
+        ChunkedStream { chunk, ops: 0 },
+        None,
+        Some(opts),
+        clock.clone(),
+    );
+    let constraints = Arc::new(Constraints::default());
+    limiter.set_observer(Some(constraints.clone()));
+    (limiter, constraints)
+}
+
+#[test]
+fn small_operations_wait_for_iops() {
+    let clock = Arc::new(ManualClock::new());
+    // 1000 B/s, but only 10 operations per second with bursts of 10
+    let mut opts = LimiterOptions::new(1000, Duration::from_secs(1), 1000);
+    opts.set_iops(10, Duration::from_secs(1), 10);
+    let (mut limiter, constraints) = limiter(&clock, 10, opts);
+    clock.sleep(Duration::from_secs(10));
+
+    // 30 operations of 10 bytes: the 10 first at once, then 10 per second
+    assert_eq!(limiter.write(&[0u8; 300]).unwrap(), 300);
+    assert_eq!(clock.elapsed(), Duration::from_secs(12));
+    assert_eq!(limiter.stream.ops, 30);
+    let constraints = constraints.0.lock().unwrap();
+    assert_eq!(constraints.len(), 20);
+    assert!(constraints.iter().all(|c| *c == Constraint::Operations));
+    let stats = limiter.stats().1;
+    assert_eq!(stats.throttled, 1);
+    assert_eq!(stats.ops_throttled, 1);
+}
+
+#[test]
+fn large_operations_wait_for_bytes() {
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(100, Duration::from_secs(1), 100);
+    opts.set_iops(10, Duration::from_secs(1), 10);
+    let (mut limiter, constraints) = limiter(&clock, usize::MAX, opts);
+    clock.sleep(Duration::from_secs(10));
+
+    assert_eq!(limiter.write(&[0u8; 300]).unwrap(), 300);
+    assert_eq!(clock.elapsed(), Duration::from_secs(12));
+    assert_eq!(limiter.stream.ops, 3);
+    assert_eq!(*constraints.0.lock().unwrap(), vec![Constraint::Bytes; 2]);
+    let stats = limiter.stats().1;
+    assert_eq!(stats.throttled, 1);
+    assert_eq!(stats.ops_throttled, 0);
+}
+
+#[test]
+fn iops_kept_on_reconfigure() {
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(1000, Duration::from_secs(1), 1000);
+    opts.set_iops(10, Duration::from_secs(1), 10);
+    let (mut limiter, _) = limiter(&clock, 10, opts);
+    clock.sleep(Duration::from_secs(10));
+    assert_eq!(limiter.write(&[0u8; 50]).unwrap(), 50);
+
+    // The 5 operations left are kept with the new limit of 1 operation per second
+    let mut opts = LimiterOptions::new(1000, Duration::from_secs(1), 1000);
+    opts.set_iops(1, Duration::from_secs(1), 10);
+    limiter.set_write_options(Some(opts));
+    assert_eq!(limiter.write(&[0u8; 70]).unwrap(), 70);
+    assert_eq!(clock.elapsed(), Duration::from_secs(12));
+}
+
+#[test]
+fn iops_not_dividing_the_window() {
+    let clock = Arc::new(ManualClock::new());
+    // 3 operations per second, an operation every 333.33.. ms
+    let mut opts = LimiterOptions::new(1000, Duration::from_secs(1), 1000);
+    opts.set_iops(3, Duration::from_secs(1), 1);
+    let (mut limiter, _) = limiter(&clock, 10, opts);
+    clock.sleep(Duration::from_secs(10));
+
+    // 31 operations: the first at once, then 3 per second
+    limiter.write_all(&[0u8; 310]).unwrap();
+    assert_eq!(limiter.stream.ops, 31);
+    let elapsed = clock.elapsed() - Duration::from_secs(10);
+    assert!(elapsed >= Duration::from_secs(10), "{elapsed:?}");
+    assert!(elapsed < Duration::from_millis(10_001), "{elapsed:?}");
+}
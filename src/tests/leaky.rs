@@ -0,0 +1,82 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::utils::{recording_limiter, Recorder};
+use crate::{Clock, Limiter, LimiterMode, LimiterOptions, ManualClock};
+
+fn leaky_limiter(clock: &Arc<ManualClock>) -> Limiter<Recorder> {
+    // 1000 B/s by chunks of 100 bytes, the bucket size doesn't allow any burst
+    let mut opts = LimiterOptions::new(1000, Duration::from_secs(1), 10_000);
+    opts.set_mode(LimiterMode::LeakyBucket { chunk_size: 100 });
+    recording_limiter(clock, opts)
+}
+
+#[test]
+fn evenly_spaced_chunks() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = leaky_limiter(&clock);
+    assert_eq!(limiter.write(&[0u8; 1000]).unwrap(), 1000);
+    let expected: Vec<(Duration, usize)> = (1..=10)
+        .map(|i| (Duration::from_millis(100) * i, 100))
+        .collect();
+    assert_eq!(limiter.stream.writes, expected);
+}
+
+#[test]
+fn no_burst_after_idle() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = leaky_limiter(&clock);
+    clock.sleep(Duration::from_secs(10));
+    // Only one chunk was gathered while idle
+    assert_eq!(limiter.write(&[0u8; 250]).unwrap(), 250);
+    assert_eq!(
+        limiter.stream.writes,
+        vec![
+            (Duration::from_millis(10_000), 100),
+            (Duration::from_millis(10_100), 100),
+            (Duration::from_millis(10_150), 50),
+        ]
+    );
+}
+
+#[test]
+fn min_operation_size_overridden_by_chunk() {
+    let mut opts = LimiterOptions::new(1000, Duration::from_secs(1), 10_000);
+    opts.set_mode(LimiterMode::LeakyBucket { chunk_size: 100 });
+    opts.set_min_operation_size(200);
+    assert_eq!(opts.sleep_threshold, 100);
+}
+
+#[test]
+fn chunks_capped_by_peak_mtu() {
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(1000, Duration::from_secs(1), 10_000);
+    opts.set_peak_rate(2000, Duration::from_secs(1), 50);
+    opts.set_mode(LimiterMode::LeakyBucket { chunk_size: 100 });
+    assert_eq!((opts.stream_cap_limit, opts.sleep_threshold), (50, 50));
+    let mut limiter = recording_limiter(&clock, opts);
+
+    // The chunks are split at the MTU, the average follows the sustained rate
+    assert_eq!(limiter.write(&[0u8; 200]).unwrap(), 200);
+    let expected: Vec<(Duration, usize)> = (1..=4)
+        .map(|i| (Duration::from_millis(50) * i, 50))
+        .collect();
+    assert_eq!(limiter.stream.writes, expected);
+}
+
+#[test]
+fn leaving_leaky_bucket_restores_limits() {
+    let mut opts = LimiterOptions::new(100, Duration::from_secs(1), 10_000);
+    opts.set_min_operation_size(500);
+    let (stream_cap_limit, sleep_threshold) = (opts.stream_cap_limit, opts.sleep_threshold);
+    assert_eq!((stream_cap_limit, sleep_threshold), (100, 500));
+
+    opts.set_mode(LimiterMode::LeakyBucket { chunk_size: 50 });
+    assert_eq!((opts.stream_cap_limit, opts.sleep_threshold), (50, 50));
+    opts.set_mode(LimiterMode::TokenBucket);
+    assert_eq!(
+        (opts.stream_cap_limit, opts.sleep_threshold),
+        (stream_cap_limit, sleep_threshold)
+    );
+}
@@ -0,0 +1,66 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{Clock, Limiter, LimiterOptions, ManualClock, ThroughputMeter};
+
+/// Transfers by chunks make the short averages oscillate around the rate
+fn assert_close(value: f64, expected: f64) {
+    assert!(
+        (value - expected).abs() <= expected * 0.1,
+        "{value} isn't close to {expected}"
+    );
+}
+
+#[test]
+fn constant_rate_and_decay() {
+    let start = Instant::now();
+    let mut meter = ThroughputMeter::new(&[Duration::from_secs(1), Duration::from_secs(10)]);
+    // 1000 bytes per second during 60 seconds, in chunks of 100 bytes
+    for i in 1..=600 {
+        meter.record(100, start + Duration::from_millis(i * 100));
+    }
+    let end = start + Duration::from_secs(60);
+    assert_close(meter.rate(Duration::from_secs(1), end).unwrap(), 1000.0);
+    assert_close(meter.rate(Duration::from_secs(10), end).unwrap(), 1000.0);
+    assert!(meter.rate(Duration::from_secs(60), end).is_none());
+
+    // After a second of silence, the short horizon forgets faster than the long one
+    let rates = meter.rates(end + Duration::from_secs(1));
+    assert_close(rates[0].1, 1000.0 / std::f64::consts::E);
+    assert_close(rates[1].1, 1000.0 * (-0.1f64).exp());
+}
+
+#[test]
+fn saturated_limiter() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(Vec::new()),
+        None,
+        Some(LimiterOptions::new(100, Duration::from_secs(1), 100)),
+        clock.clone(),
+    );
+    limiter.set_throughput_horizons(&[Duration::from_secs(1), Duration::from_secs(60)]);
+    let handle = limiter.handle();
+    let start = clock.now();
+    while clock.now().saturating_duration_since(start) < Duration::from_secs(10) {
+        assert_eq!(limiter.write(&[0u8; 10]).unwrap(), 10);
+    }
+
+    let (read, write) = limiter.throughput();
+    assert_eq!(
+        read,
+        vec![
+            (Duration::from_secs(1), 0.0),
+            (Duration::from_secs(60), 0.0)
+        ]
+    );
+    assert_close(write[0].1, 100.0);
+    // Still warming up after 10s of the 60s horizon
+    assert!(write[1].1 < 20.0);
+    assert_eq!(handle.throughput(), (read, write));
+
+    let (read, write) = limiter.utilization();
+    assert!(read.is_none());
+    assert_close(write.unwrap()[0].1, 1.0);
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use metrics::{
+    Counter, CounterFn, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+
+use crate::{Limiter, LimiterOptions, ManualClock};
+
+struct TestCounter(AtomicU64);
+
+impl CounterFn for TestCounter {
+    fn increment(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn absolute(&self, value: u64) {
+        self.0.fetch_max(value, Ordering::Relaxed);
+    }
+}
+
+struct TestHistogram(Mutex<Vec<f64>>);
+
+impl HistogramFn for TestHistogram {
+    fn record(&self, value: f64) {
+        self.0.lock().unwrap().push(value);
+    }
+}
+
+/// Keeps the metrics by name and labels, as "name{label=value,...}"
+#[derive(Default)]
+struct TestRecorder {
+    counters: Mutex<HashMap<String, Arc<TestCounter>>>,
+    histograms: Mutex<HashMap<String, Arc<TestHistogram>>>,
+}
+
+fn key_string(key: &Key) -> String {
+    let labels: Vec<String> = key
+        .labels()
+        .map(|label| format!("{}={}", label.key(), label.value()))
+        .collect();
+    format!("{}{{{}}}", key.name(), labels.join(","))
+}
+
+impl TestRecorder {
+    fn counter(&self, key: &str) -> u64 {
+        self.counters.lock().unwrap()[key].0.load(Ordering::Relaxed)
+    }
+
+    fn histogram(&self, key: &str) -> Vec<f64> {
+        self.histograms.lock().unwrap()[key]
+            .0
+            .lock()
+            .unwrap()
+            .clone()
+    }
+}
+
+impl Recorder for TestRecorder {
+    fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        let counter = self
+            .counters
+            .lock()
+            .unwrap()
+            .entry(key_string(key))
+            .or_insert_with(|| Arc::new(TestCounter(AtomicU64::new(0))))
+            .clone();
+        Counter::from_arc(counter)
+    }
+
+    fn register_gauge(&self, _key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        Gauge::noop()
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+        let histogram = self
+            .histograms
+            .lock()
+            .unwrap()
+            .entry(key_string(key))
+            .or_insert_with(|| Arc::new(TestHistogram(Mutex::new(Vec::new()))))
+            .clone();
+        Histogram::from_arc(histogram)
+    }
+}
+
+#[test]
+fn write_metrics() {
+    let recorder = TestRecorder::default();
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(2, Duration::from_secs(1), 2);
+    opts.set_timeout(Duration::from_secs(3));
+    let mut limiter =
+        Limiter::with_clock(std::io::Cursor::new(Vec::new()), None, Some(opts), clock);
+    limiter.set_name("peer-1");
+
+    metrics::with_local_recorder(&recorder, || {
+        assert_eq!(limiter.write(&[0u8; 4]).unwrap(), 4);
+        assert_eq!(
+            limiter.write(&[0u8; 10]).unwrap_err().kind(),
+            std::io::ErrorKind::TimedOut
+        );
+    });
+
+    let labels = "{name=peer-1,direction=write}";
+    assert_eq!(
+        recorder.counter(&format!("stream_limiter_bytes{labels}")),
+        8
+    );
+    assert_eq!(
+        recorder.counter(&format!("stream_limiter_sleeps{labels}")),
+        5
+    );
+    assert_eq!(
+        recorder.counter(&format!("stream_limiter_timeouts{labels}")),
+        1
+    );
+    assert_eq!(
+        recorder.counter(&format!("stream_limiter_io_errors{labels}")),
+        0
+    );
+    assert_eq!(
+        recorder.histogram(&format!("stream_limiter_chunk_bytes{labels}")),
+        vec![2.0; 4]
+    );
+    assert_eq!(
+        recorder
+            .histogram(&format!("stream_limiter_sleep_seconds{labels}"))
+            .iter()
+            .sum::<f64>(),
+        5.0
+    );
+    // The read metrics are registered, but nothing was read
+    assert_eq!(
+        recorder.counter("stream_limiter_bytes{name=peer-1,direction=read}"),
+        0
+    );
+}
@@ -0,0 +1,115 @@
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token};
+
+use super::utils::assert_checksum_samedata;
+use crate::{poll_timeout, Limiter, LimiterOptions};
+
+#[test]
+fn fold_deadlines() {
+    let now = Instant::now();
+    let deadlines = [
+        None,
+        Some(now + Duration::from_secs(3)),
+        Some(now + Duration::from_secs(1)),
+    ];
+    assert_eq!(
+        poll_timeout(deadlines, None, now),
+        Some(Duration::from_secs(1))
+    );
+    assert_eq!(
+        poll_timeout(deadlines, Some(Duration::from_millis(500)), now),
+        Some(Duration::from_millis(500))
+    );
+    assert_eq!(poll_timeout([None, None], None, now), None);
+    // A passed deadline doesn't block at all
+    assert_eq!(
+        poll_timeout([Some(now)], None, now + Duration::from_secs(1)),
+        Some(Duration::ZERO)
+    );
+}
+
+#[test]
+fn throttled_write_in_poll_loop() {
+    const CLIENT: Token = Token(0);
+    let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut limiter = Limiter::new(
+        stream,
+        None,
+        Some(LimiterOptions::new(100, Duration::from_secs(1), 10)),
+    );
+
+    let mut poll = Poll::new().unwrap();
+    let mut events = Events::with_capacity(16);
+    poll.registry()
+        .register(&mut limiter, CLIENT, Interest::WRITABLE)
+        .unwrap();
+
+    let start = Instant::now();
+    let data = [8u8; 50];
+    let mut written = 0;
+    while written < data.len() {
+        let timeout = poll_timeout([limiter.next_ready_at()], None, Instant::now());
+        poll.poll(&mut events, timeout).unwrap();
+        // Retry on socket events and once the throttling deadline is passed
+        loop {
+            match limiter.write(&data[written..]) {
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // Not connected yet
+                Err(e) if e.kind() == ErrorKind::NotConnected => break,
+                Err(e) => panic!("{e}"),
+            }
+            if written == data.len() {
+                break;
+            }
+        }
+    }
+    // 50 bytes at 100 bytes per second, starting with an empty bucket
+    assert!(start.elapsed() >= Duration::from_millis(450));
+    poll.registry().deregister(&mut limiter).unwrap();
+    drop(limiter);
+
+    let (mut server, _) = listener.accept().unwrap();
+    server.set_nodelay(true).unwrap();
+    let mut received = vec![];
+    loop {
+        let mut buf = [0u8; 64];
+        match server.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => received.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(10))
+            }
+            Err(e) => panic!("{e}"),
+        }
+    }
+    assert_checksum_samedata::<50>(&received, 8);
+}
+
+#[test]
+fn deregister_restores_blocking_mode() {
+    let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let mut limiter = Limiter::new(stream, None, None);
+    let poll = Poll::new().unwrap();
+
+    poll.registry()
+        .register(&mut limiter, Token(0), Interest::WRITABLE)
+        .unwrap();
+    assert!(limiter.nonblocking);
+    poll.registry().deregister(&mut limiter).unwrap();
+    assert!(!limiter.nonblocking);
+
+    // A limiter already non-blocking stays so
+    limiter.set_nonblocking(true);
+    poll.registry()
+        .register(&mut limiter, Token(0), Interest::WRITABLE)
+        .unwrap();
+    poll.registry().deregister(&mut limiter).unwrap();
+    assert!(limiter.nonblocking);
+}
@@ -1,7 +1,37 @@
 #[allow(dead_code)]
 pub mod utils;
 
+mod algorithm;
+mod cancel;
+mod clock;
+mod fairness;
+#[cfg(feature = "futures-io")]
+mod futures_io;
+mod gcra;
+mod handle;
+mod htb;
+mod iops;
+mod leaky;
+mod meter;
+#[cfg(feature = "metrics")]
+mod metrics_export;
+#[cfg(feature = "mio")]
+mod mio_source;
 mod network;
+mod nonblocking;
+mod observer;
 mod parametric;
+mod peak;
 mod read;
+mod reconfigure;
+#[cfg(feature = "mio")]
+mod scheduler;
+mod shared;
+mod sliding;
+mod stats;
+mod token_bucket;
+#[cfg(feature = "tokio")]
+mod tokio_io;
+#[cfg(feature = "tracing")]
+mod tracing_events;
 mod write;
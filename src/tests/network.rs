@@ -1,3 +1,6 @@
+// These tests write fixed size buffers on purpose, and accept a single connection
+#![allow(clippy::unused_io_amount, clippy::never_loop)]
+
 use crate::{Limiter, LimiterOptions};
 use std::io::{Read, Write};
 use std::net::{TcpListener, TcpStream};
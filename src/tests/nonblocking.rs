@@ -0,0 +1,141 @@
+use std::io::{ErrorKind, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::utils::assert_checksum_samedata;
+use crate::{Clock, Limiter, LimiterOptions, ManualClock, SharedBucket};
+
+fn limiter(
+    clock: &Arc<ManualClock>,
+    write_opt: LimiterOptions,
+) -> Limiter<std::io::Cursor<Vec<u8>>> {
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(vec![]),
+        None,
+        Some(write_opt),
+        clock.clone(),
+    );
+    limiter.set_nonblocking(true);
+    limiter
+}
+
+#[test]
+fn would_block_until_ready() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = limiter(&clock, LimiterOptions::new(1, Duration::from_secs(1), 1));
+    assert_eq!(limiter.next_ready_at(), None);
+
+    let err = limiter.write(&[1u8; 3]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    // The limiter never moves the time forward
+    assert_eq!(clock.elapsed(), Duration::ZERO);
+    let ready_at = limiter.next_ready_at().unwrap();
+    assert_eq!(ready_at, clock.now() + Duration::from_secs(1));
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(limiter.write(&[1u8; 3]).unwrap(), 1);
+    assert_eq!(limiter.next_ready_at(), None);
+}
+
+#[test]
+fn partial_write() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = limiter(&clock, LimiterOptions::new(1, Duration::from_secs(1), 5));
+    clock.advance(Duration::from_secs(10));
+
+    // Only the 5 tokens of the bucket are spent
+    assert_eq!(limiter.write(&[2u8; 10]).unwrap(), 5);
+    assert_eq!(
+        limiter.write(&[2u8; 5]).unwrap_err().kind(),
+        ErrorKind::WouldBlock
+    );
+    assert_eq!(
+        limiter.next_ready_at(),
+        Some(clock.now() + Duration::from_secs(1))
+    );
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(limiter.write(&[2u8; 5]).unwrap(), 5);
+    assert_eq!(clock.elapsed(), Duration::from_secs(15));
+    assert_checksum_samedata::<10>(&limiter.stream.into_inner(), 2);
+}
+
+#[test]
+fn min_operation_size() {
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(1, Duration::from_secs(1), 10);
+    opts.set_min_operation_size(4);
+    let mut limiter = limiter(&clock, opts);
+    clock.advance(Duration::from_secs(3));
+    assert_eq!(
+        limiter.write(&[2u8; 10]).unwrap_err().kind(),
+        ErrorKind::WouldBlock
+    );
+    assert_eq!(
+        limiter.next_ready_at(),
+        Some(clock.now() + Duration::from_secs(1))
+    );
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(limiter.write(&[2u8; 10]).unwrap(), 4);
+}
+
+#[test]
+fn shared_bucket_spent() {
+    let clock = Arc::new(ManualClock::new());
+    let bucket = Arc::new(SharedBucket::with_clock(
+        LimiterOptions::new(1, Duration::from_secs(1), 2),
+        clock.clone(),
+    ));
+    let mut first = Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(bucket.clone()));
+    let mut second = Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(bucket));
+    second.set_nonblocking(true);
+
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(first.write(&[1u8; 2]).unwrap(), 2);
+    assert_eq!(
+        second.write(&[1u8; 1]).unwrap_err().kind(),
+        ErrorKind::WouldBlock
+    );
+    assert_eq!(
+        second.next_ready_at(),
+        Some(clock.now() + Duration::from_secs(1))
+    );
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(second.write(&[1u8; 1]).unwrap(), 1);
+}
+
+#[test]
+fn idle_after_would_block_on_shared_bucket() {
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(1, Duration::from_secs(1), 2);
+    opts.set_timeout(Duration::from_secs(10));
+    let bucket = Arc::new(SharedBucket::with_clock(opts, clock.clone()));
+    let mut idle = Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(bucket.clone()));
+    let mut blocking = Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(bucket));
+    idle.set_nonblocking(true);
+
+    // Throttled, then never retried
+    assert_eq!(
+        idle.write(&[1u8; 1]).unwrap_err().kind(),
+        ErrorKind::WouldBlock
+    );
+    // Its place in the queue isn't kept, the other limiter gets the tokens
+    assert_eq!(blocking.write(&[1u8; 1]).unwrap(), 1);
+    assert_eq!(clock.elapsed(), Duration::from_secs(1));
+}
+
+#[test]
+fn paused() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = limiter(&clock, LimiterOptions::new(10, Duration::from_secs(1), 10));
+    let handle = limiter.handle();
+    handle.pause_write();
+    assert_eq!(
+        limiter.write(&[1u8; 1]).unwrap_err().kind(),
+        ErrorKind::WouldBlock
+    );
+    assert!(limiter.next_ready_at().is_some());
+    assert_eq!(clock.elapsed(), Duration::ZERO);
+    handle.resume_write();
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(limiter.write(&[1u8; 1]).unwrap(), 1);
+}
@@ -0,0 +1,143 @@
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{Direction, Limiter, LimiterObserver, LimiterOptions, ManualClock};
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Sleep(Direction, Duration, u64),
+    Transfer(Direction, u64),
+    Timeout(Direction, Duration),
+    IoError(Direction, ErrorKind),
+}
+
+#[derive(Default)]
+struct Recorder(Mutex<Vec<Event>>);
+
+impl Recorder {
+    fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl LimiterObserver for Recorder {
+    fn on_sleep(&self, dir: Direction, duration: Duration, tokens: u64) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(Event::Sleep(dir, duration, tokens));
+    }
+
+    fn on_transfer(&self, dir: Direction, bytes: u64) {
+        self.0.lock().unwrap().push(Event::Transfer(dir, bytes));
+    }
+
+    fn on_timeout(&self, dir: Direction, elapsed: Duration) {
+        self.0.lock().unwrap().push(Event::Timeout(dir, elapsed));
+    }
+
+    fn on_io_error(&self, dir: Direction, error: &std::io::Error) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(Event::IoError(dir, error.kind()));
+    }
+}
+
+/// Stream failing on every operation
+struct BrokenStream;
+
+impl Read for BrokenStream {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Err(ErrorKind::ConnectionReset.into())
+    }
+}
+
+impl Write for BrokenStream {
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        Err(ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn sleeps_and_transfers() {
+    let clock = Arc::new(ManualClock::new());
+    let recorder = Arc::new(Recorder::default());
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(vec![0u8; 2]),
+        Some(LimiterOptions::new(1, Duration::from_secs(1), 1)),
+        None,
+        clock,
+    );
+    limiter.set_observer(Some(recorder.clone()));
+    let mut buf = [0u8; 3];
+    assert_eq!(limiter.read(&mut buf).unwrap(), 2);
+    assert_eq!(
+        recorder.take(),
+        vec![
+            Event::Sleep(Direction::Read, Duration::from_secs(1), 1),
+            Event::Transfer(Direction::Read, 1),
+            Event::Sleep(Direction::Read, Duration::from_secs(1), 1),
+            Event::Transfer(Direction::Read, 1),
+            Event::Sleep(Direction::Read, Duration::from_secs(1), 1),
+            Event::Transfer(Direction::Read, 0),
+        ]
+    );
+
+    // Removing the observer stops the events
+    limiter.set_observer(None);
+    assert_eq!(limiter.read(&mut buf).unwrap(), 0);
+    assert!(recorder.take().is_empty());
+}
+
+#[test]
+fn timeout() {
+    let clock = Arc::new(ManualClock::new());
+    let recorder = Arc::new(Recorder::default());
+    let mut opts = LimiterOptions::new(1, Duration::from_secs(1), 1);
+    opts.set_timeout(Duration::from_millis(2500));
+    let mut limiter =
+        Limiter::with_clock(std::io::Cursor::new(Vec::new()), None, Some(opts), clock);
+    limiter.set_observer(Some(recorder.clone()));
+    assert_eq!(
+        limiter.write(&[0u8; 5]).unwrap_err().kind(),
+        ErrorKind::TimedOut
+    );
+    let events = recorder.take();
+    assert_eq!(events.len(), 6);
+    assert_eq!(
+        events[4],
+        Event::Sleep(Direction::Write, Duration::from_millis(500), 1)
+    );
+    assert_eq!(
+        events[5],
+        Event::Timeout(Direction::Write, Duration::from_millis(2500))
+    );
+}
+
+#[test]
+fn io_errors() {
+    let recorder = Arc::new(Recorder::default());
+    let mut limiter = Limiter::new(
+        BrokenStream,
+        None,
+        Some(LimiterOptions::new(10, Duration::from_millis(10), 10)),
+    );
+    limiter.set_observer(Some(recorder.clone()));
+    assert!(limiter.read(&mut [0u8; 5]).is_err());
+    assert!(limiter.write(&[0u8; 5]).is_err());
+    let events = recorder.take();
+    assert_eq!(
+        events[0],
+        Event::IoError(Direction::Read, ErrorKind::ConnectionReset)
+    );
+    assert_eq!(
+        events.last(),
+        Some(&Event::IoError(Direction::Write, ErrorKind::BrokenPipe))
+    );
+}
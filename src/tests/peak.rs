@@ -0,0 +1,102 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::utils::recording_limiter;
+use crate::{Clock, LimiterOptions, ManualClock};
+
+#[test]
+fn full_bucket_drains_at_peak_rate() {
+    let clock = Arc::new(ManualClock::new());
+    // 100 B/s with bursts of 1000 bytes, sent at 1000 B/s by chunks of 100 bytes
+    let mut opts = LimiterOptions::new(100, Duration::from_secs(1), 1000);
+    opts.set_peak_rate(1000, Duration::from_secs(1), 100);
+    let mut limiter = recording_limiter(&clock, opts);
+    // Fill the bucket
+    clock.sleep(Duration::from_secs(10));
+
+    assert_eq!(limiter.write(&[0u8; 1000]).unwrap(), 1000);
+    let writes = &limiter.stream.writes;
+    assert_eq!(writes.len(), 10);
+    for (i, (at, len)) in writes.iter().enumerate() {
+        assert_eq!(*len, 100);
+        assert_eq!(
+            *at,
+            Duration::from_secs(10) + Duration::from_millis(100) * i as u32
+        );
+    }
+}
+
+#[test]
+fn average_follows_sustained_rate() {
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(100, Duration::from_secs(1), 1000);
+    opts.set_peak_rate(1000, Duration::from_secs(1), 100);
+    let mut limiter = recording_limiter(&clock, opts);
+    clock.sleep(Duration::from_secs(10));
+
+    // The burst takes 0.9s, the rest of the data comes at 100 B/s
+    assert_eq!(limiter.write(&[0u8; 3000]).unwrap(), 3000);
+    let elapsed = clock.elapsed() - Duration::from_secs(10);
+    assert!(
+        elapsed >= Duration::from_secs(19) && elapsed <= Duration::from_secs(21),
+        "{elapsed:?}"
+    );
+    assert!(limiter.stream.writes.iter().all(|(_, len)| *len <= 100));
+}
+
+#[test]
+fn reconfigure_keeps_peak_tokens() {
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(100, Duration::from_secs(1), 1000);
+    opts.set_peak_rate(1000, Duration::from_secs(1), 100);
+    let mut limiter = recording_limiter(&clock, opts.clone());
+    clock.sleep(Duration::from_secs(10));
+
+    // Setting the options again doesn't give a new MTU burst
+    for _ in 0..3 {
+        limiter.set_write_options(Some(opts.clone()));
+        assert_eq!(limiter.write(&[0u8; 100]).unwrap(), 100);
+    }
+    let writes = &limiter.stream.writes;
+    for (i, (at, _)) in writes.iter().enumerate() {
+        assert_eq!(
+            *at,
+            Duration::from_secs(10) + Duration::from_millis(100) * i as u32
+        );
+    }
+
+    // Removing the peak rate and adding it back refills the peak bucket
+    limiter.set_write_options(Some(LimiterOptions::new(100, Duration::from_secs(1), 1000)));
+    limiter.set_write_options(Some(opts));
+    assert_eq!(limiter.write(&[0u8; 100]).unwrap(), 100);
+    assert_eq!(
+        limiter.stream.writes.last().unwrap().0,
+        Duration::from_millis(10_200)
+    );
+}
+
+#[test]
+fn no_peak_sends_burst_at_once() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = recording_limiter(
+        &clock,
+        LimiterOptions::new(100, Duration::from_secs(1), 1000),
+    );
+    clock.sleep(Duration::from_secs(10));
+    assert_eq!(limiter.write(&[0u8; 1000]).unwrap(), 1000);
+    assert_eq!(limiter.stream.writes, vec![(Duration::from_secs(10), 1000)]);
+}
+
+#[test]
+fn min_operation_size_capped_by_mtu() {
+    let mut opts = LimiterOptions::new(100, Duration::from_secs(1), 1000);
+    opts.set_peak_rate(1000, Duration::from_secs(1), 100);
+    opts.set_min_operation_size(200);
+    assert_eq!(opts.sleep_threshold, 100);
+
+    let mut opts = LimiterOptions::new(100, Duration::from_secs(1), 1000);
+    opts.set_min_operation_size(200);
+    opts.set_peak_rate(1000, Duration::from_secs(1), 100);
+    assert_eq!(opts.sleep_threshold, 100);
+}
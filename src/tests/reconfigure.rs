@@ -0,0 +1,92 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::utils::assert_checksum_samedata;
+use crate::{Clock, Limiter, LimiterOptions, ManualClock};
+
+fn limiter(
+    clock: &Arc<ManualClock>,
+    write_opt: Option<LimiterOptions>,
+) -> Limiter<std::io::Cursor<Vec<u8>>> {
+    Limiter::with_clock(std::io::Cursor::new(vec![]), None, write_opt, clock.clone())
+}
+
+#[test]
+fn change_rate_between_writes() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = limiter(
+        &clock,
+        Some(LimiterOptions::new(1, Duration::from_secs(1), 10)),
+    );
+    assert_eq!(limiter.write(&[5u8; 5]).unwrap(), 5);
+    assert_eq!(clock.elapsed(), Duration::from_secs(5));
+
+    limiter.set_write_options(Some(LimiterOptions::new(5, Duration::from_secs(1), 10)));
+    assert_eq!(limiter.write(&[5u8; 10]).unwrap(), 10);
+    assert_eq!(clock.elapsed(), Duration::from_secs(7));
+    assert_checksum_samedata::<15>(&limiter.stream.into_inner(), 5);
+}
+
+#[test]
+fn tokens_carry_over() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = limiter(
+        &clock,
+        Some(LimiterOptions::new(10, Duration::from_secs(1), 100)),
+    );
+    clock.advance(Duration::from_secs(5));
+
+    // The 50 tokens gathered at the previous rate are still there
+    limiter.set_write_options(Some(LimiterOptions::new(1, Duration::from_secs(1), 100)));
+    assert_eq!(limiter.write(&[1u8; 50]).unwrap(), 50);
+    assert_eq!(clock.elapsed(), Duration::from_secs(5));
+}
+
+#[test]
+fn tokens_capped_by_new_bucket_size() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = limiter(
+        &clock,
+        Some(LimiterOptions::new(10, Duration::from_secs(1), 100)),
+    );
+    clock.advance(Duration::from_secs(5));
+
+    limiter.set_write_options(Some(LimiterOptions::new(10, Duration::from_secs(1), 20)));
+    assert_eq!(limiter.write(&[1u8; 20]).unwrap(), 20);
+    assert_eq!(clock.elapsed(), Duration::from_secs(5));
+    assert_eq!(limiter.write(&[1u8; 10]).unwrap(), 10);
+    assert_eq!(clock.elapsed(), Duration::from_secs(6));
+}
+
+#[test]
+fn enable_and_disable_limit() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = limiter(&clock, None);
+    assert!(!limiter.limits().1);
+    clock.advance(Duration::from_secs(5));
+
+    // Enabling the limit starts with an empty bucket
+    limiter.set_write_options(Some(LimiterOptions::new(2, Duration::from_secs(1), 10)));
+    assert!(limiter.limits().1);
+    assert_eq!(limiter.write(&[3u8; 4]).unwrap(), 4);
+    assert_eq!(clock.elapsed(), Duration::from_secs(7));
+
+    limiter.set_write_options(None);
+    assert!(!limiter.limits().1);
+    assert_eq!(limiter.write(&[3u8; 100]).unwrap(), 100);
+    assert_eq!(clock.elapsed(), Duration::from_secs(7));
+}
+
+#[test]
+fn options_set_after_creation() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = limiter(&clock, None);
+    assert!(limiter.write_options().is_none());
+    clock.sleep(Duration::from_secs(5));
+    // Starts with an empty bucket, like a new limiter
+    limiter.set_write_options(Some(LimiterOptions::new(2, Duration::from_secs(1), 10)));
+    assert_eq!(limiter.write_options().unwrap().window_length, 2);
+    assert_eq!(limiter.write(&[3u8; 4]).unwrap(), 4);
+    assert_eq!(clock.elapsed(), Duration::from_secs(7));
+}
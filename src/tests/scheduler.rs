@@ -0,0 +1,265 @@
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mio::net::TcpStream;
+
+use super::utils::assert_checksum_samedata;
+use crate::{CancelToken, Limiter, LimiterOptions, LimiterScheduler, ManualClock, SchedulerEvent};
+
+#[test]
+fn writes_at_each_stream_rate() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let mut received = vec![];
+        for _ in 0..2 {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut data = vec![];
+            stream.read_to_end(&mut data).unwrap();
+            received.push(data);
+        }
+        received
+    });
+
+    let mut scheduler = LimiterScheduler::new().unwrap();
+    let fast = scheduler
+        .add(Limiter::new(
+            TcpStream::connect(addr).unwrap(),
+            None,
+            Some(LimiterOptions::new(1000, Duration::from_secs(1), 100)),
+        ))
+        .unwrap();
+    let slow = scheduler
+        .add(Limiter::new(
+            TcpStream::connect(addr).unwrap(),
+            None,
+            Some(LimiterOptions::new(200, Duration::from_secs(1), 10)),
+        ))
+        .unwrap();
+    assert_eq!(scheduler.len(), 2);
+    assert!(scheduler.send(fast, &[1u8; 100]));
+    assert!(scheduler.send(slow, &[2u8; 100]));
+
+    let start = Instant::now();
+    let mut fast_done = None;
+    while scheduler.pending_output(slow) > 0 {
+        assert!(scheduler
+            .run_once(Some(Duration::from_secs(1)))
+            .unwrap()
+            .is_empty());
+        if fast_done.is_none() && scheduler.pending_output(fast) == 0 {
+            fast_done = Some(start.elapsed());
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+    let slow_done = start.elapsed();
+    assert!(fast_done.unwrap() < slow_done);
+    assert!(slow_done >= Duration::from_millis(450));
+
+    // Close the connections
+    scheduler.remove(fast).unwrap();
+    scheduler.remove(slow).unwrap();
+    assert!(scheduler.is_empty());
+    for data in server.join().unwrap() {
+        assert_eq!(data.len(), 100);
+        assert_checksum_samedata::<100>(&data, data[0]);
+    }
+}
+
+#[test]
+fn reads_until_closed() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(&[5u8; 60]).unwrap();
+    });
+
+    let mut scheduler = LimiterScheduler::new().unwrap();
+    let id = scheduler
+        .add(Limiter::new(
+            TcpStream::connect(addr).unwrap(),
+            Some(LimiterOptions::new(100, Duration::from_secs(1), 20)),
+            None,
+        ))
+        .unwrap();
+
+    let start = Instant::now();
+    let mut received = vec![];
+    let mut closed = false;
+    while !closed {
+        for event in scheduler.run_once(Some(Duration::from_secs(1))).unwrap() {
+            match event {
+                SchedulerEvent::Data(from, data) => {
+                    assert_eq!(from, id);
+                    received.extend(data);
+                }
+                SchedulerEvent::Closed(from) => {
+                    assert_eq!(from, id);
+                    closed = true;
+                }
+                SchedulerEvent::Error(_, e) => panic!("{e}"),
+            }
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+    // 60 bytes at 100 bytes per second, starting with an empty bucket
+    assert!(start.elapsed() >= Duration::from_millis(550));
+    assert_checksum_samedata::<60>(&received, 5);
+    assert!(scheduler.is_empty());
+    server.join().unwrap();
+}
+
+#[test]
+fn answers_after_half_close() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(&[6u8; 10]).unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut data = vec![];
+        stream.read_to_end(&mut data).unwrap();
+        data
+    });
+
+    let mut scheduler = LimiterScheduler::new().unwrap();
+    let id = scheduler
+        .add(Limiter::new(
+            TcpStream::connect(addr).unwrap(),
+            None,
+            Some(LimiterOptions::new(100, Duration::from_secs(1), 10)),
+        ))
+        .unwrap();
+
+    // The request is answered once the peer is done sending, the stream is only
+    // removed when the answer is written
+    let start = Instant::now();
+    let mut closed = false;
+    while !closed {
+        for event in scheduler.run_once(Some(Duration::from_secs(1))).unwrap() {
+            match event {
+                SchedulerEvent::Data(from, data) => {
+                    assert_eq!(from, id);
+                    assert!(scheduler.send(id, &vec![7u8; 3 * data.len()]));
+                }
+                SchedulerEvent::Closed(from) => {
+                    assert_eq!(from, id);
+                    closed = true;
+                }
+                SchedulerEvent::Error(_, e) => panic!("{e}"),
+            }
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+    assert!(scheduler.is_empty());
+    assert_checksum_samedata::<30>(&server.join().unwrap(), 7);
+}
+
+#[test]
+fn cancelled_stream_is_removed() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut data = vec![];
+        stream.read_to_end(&mut data).unwrap();
+    });
+
+    let mut scheduler = LimiterScheduler::new().unwrap();
+    let token = CancelToken::new();
+    let mut limiter = Limiter::new(
+        TcpStream::connect(addr).unwrap(),
+        None,
+        Some(LimiterOptions::new(100, Duration::from_secs(1), 10)),
+    );
+    limiter.set_cancel_token(token.clone());
+    let id = scheduler.add(limiter).unwrap();
+    assert!(scheduler.send(id, &[3u8; 50]));
+    token.cancel();
+
+    let start = Instant::now();
+    let mut cancelled = false;
+    while !cancelled {
+        for event in scheduler
+            .run_once(Some(Duration::from_millis(100)))
+            .unwrap()
+        {
+            match event {
+                SchedulerEvent::Error(from, e) => {
+                    assert_eq!(from, id);
+                    assert_eq!(e.kind(), std::io::ErrorKind::ConnectionAborted);
+                    cancelled = true;
+                }
+                event => panic!("Unexpected event {event:?}"),
+            }
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+    assert!(scheduler.is_empty());
+    server.join().unwrap();
+}
+
+#[test]
+fn removed_stream_id_is_not_reused() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut scheduler = LimiterScheduler::new().unwrap();
+    let first = scheduler
+        .add(Limiter::new(TcpStream::connect(addr).unwrap(), None, None))
+        .unwrap();
+    scheduler.remove(first).unwrap();
+    // The new stream takes the place of the first one
+    let second = scheduler
+        .add(Limiter::new(TcpStream::connect(addr).unwrap(), None, None))
+        .unwrap();
+    assert_ne!(first, second);
+    assert!(!scheduler.send(first, &[1u8; 10]));
+    assert!(scheduler.get_mut(first).is_none());
+    assert!(scheduler.remove(first).is_none());
+    assert_eq!(scheduler.len(), 1);
+
+    assert!(scheduler.send(second, &[1u8; 10]));
+    assert_eq!(scheduler.pending_output(first), 0);
+    assert_eq!(scheduler.pending_output(second), 10);
+}
+
+#[test]
+fn deadlines_on_the_limiter_clock() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut data = vec![];
+        stream.read_to_end(&mut data).unwrap();
+        data
+    });
+
+    let clock = Arc::new(ManualClock::new());
+    let mut scheduler = LimiterScheduler::new().unwrap();
+    let id = scheduler
+        .add(Limiter::with_clock(
+            TcpStream::connect(addr).unwrap(),
+            None,
+            Some(LimiterOptions::new(10, Duration::from_secs(1), 10)),
+            clock.clone(),
+        ))
+        .unwrap();
+    assert!(scheduler.send(id, &[4u8; 10]));
+    // The bucket starts empty, nothing is written until the clock moves
+    for _ in 0..3 {
+        scheduler.run_once(Some(Duration::from_millis(50))).unwrap();
+    }
+    assert_eq!(scheduler.pending_output(id), 10);
+
+    clock.advance(Duration::from_secs(1));
+    let start = Instant::now();
+    scheduler.run_once(Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(scheduler.pending_output(id), 0);
+    assert!(start.elapsed() < Duration::from_millis(500));
+
+    scheduler.remove(id).unwrap();
+    assert_checksum_samedata::<10>(&server.join().unwrap(), 4);
+}
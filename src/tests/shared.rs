@@ -0,0 +1,165 @@
+use std::io::Write;
+use std::sync::{Arc, Barrier};
+use std::time::Duration;
+
+use super::utils::assert_checksum_samedata;
+use crate::{Limiter, LimiterMode, LimiterOptions, ManualClock, SharedBucket};
+
+#[test]
+fn shared_bucket_alternate_writes() {
+    let clock = Arc::new(ManualClock::new());
+    let bucket = Arc::new(SharedBucket::with_clock(
+        LimiterOptions::new(10, Duration::from_secs(1), 10),
+        clock.clone(),
+    ));
+    let mut limiter_a =
+        Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(bucket.clone()));
+    let mut limiter_b =
+        Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(bucket.clone()));
+    assert_eq!(limiter_a.limits(), (false, true));
+
+    // 100 bytes at 10 bytes / sec in total, whatever the limiter used
+    for _ in 0..5 {
+        assert_eq!(limiter_a.write(&[7u8; 10]).unwrap(), 10);
+        assert_eq!(limiter_b.write(&[7u8; 10]).unwrap(), 10);
+    }
+    assert_eq!(clock.elapsed(), Duration::from_secs(10));
+    assert_checksum_samedata::<50>(&limiter_a.stream.into_inner(), 7);
+    assert_checksum_samedata::<50>(&limiter_b.stream.into_inner(), 7);
+}
+
+#[test]
+fn shared_bucket_with_own_limit() {
+    let clock = Arc::new(ManualClock::new());
+    let bucket = Arc::new(SharedBucket::with_clock(
+        LimiterOptions::new(10, Duration::from_secs(1), 10),
+        clock.clone(),
+    ));
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(vec![]),
+        None,
+        Some(LimiterOptions::new(5, Duration::from_secs(1), 5)),
+        clock.clone(),
+    );
+    limiter.add_write_bucket(bucket.clone());
+
+    // The limiter's own options are more restrictive than the shared bucket
+    assert_eq!(limiter.write(&[3u8; 20]).unwrap(), 20);
+    assert_eq!(clock.elapsed(), Duration::from_secs(4));
+    // The shared bucket only got charged for what was written, and keeps the rest
+    assert_eq!(bucket.tokens_available(), 5);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(bucket.tokens_available(), 10);
+}
+
+#[test]
+fn shared_bucket_threads() {
+    const NB_THREADS: usize = 4;
+    let clock = Arc::new(ManualClock::new());
+    let bucket = Arc::new(SharedBucket::with_clock(
+        LimiterOptions::new(100, Duration::from_millis(100), 100),
+        clock.clone(),
+    ));
+    let barrier = Arc::new(Barrier::new(NB_THREADS));
+    let handles: Vec<_> = (0..NB_THREADS)
+        .map(|_| {
+            let bucket = bucket.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                let mut limiter =
+                    Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(bucket));
+                barrier.wait();
+                limiter.write_all(&[1u8; 100]).unwrap();
+                limiter.stream.into_inner().len()
+            })
+        })
+        .collect();
+    let total: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    assert_eq!(total, NB_THREADS * 100);
+    // 400 bytes at 100 bytes / 100ms, starting from an empty bucket
+    assert!(
+        clock.elapsed() >= Duration::from_millis(400),
+        "{:?}",
+        clock.elapsed()
+    );
+}
+
+#[test]
+fn hierarchy_most_restrictive_level() {
+    let clock = Arc::new(ManualClock::new());
+    let group = Arc::new(SharedBucket::with_clock(
+        LimiterOptions::new(5, Duration::from_secs(1), 5),
+        clock.clone(),
+    ));
+    let global = Arc::new(SharedBucket::with_clock(
+        LimiterOptions::new(20, Duration::from_secs(1), 20),
+        clock.clone(),
+    ));
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(vec![]),
+        None,
+        Some(LimiterOptions::new(10, Duration::from_secs(1), 10)),
+        clock.clone(),
+    );
+    limiter.add_write_bucket(group.clone());
+    limiter.add_write_bucket(global.clone());
+
+    // The peer group is the most restrictive level: 20 bytes at 5 bytes / sec
+    assert_eq!(limiter.write(&[9u8; 20]).unwrap(), 20);
+    assert_eq!(clock.elapsed(), Duration::from_secs(4));
+    assert_checksum_samedata::<20>(&limiter.stream.into_inner(), 9);
+}
+
+#[test]
+fn hierarchy_no_double_charge() {
+    let clock = Arc::new(ManualClock::new());
+    let group = Arc::new(SharedBucket::with_clock(
+        LimiterOptions::new(10, Duration::from_secs(1), 100),
+        clock.clone(),
+    ));
+    let global = Arc::new(SharedBucket::with_clock(
+        LimiterOptions::new(5, Duration::from_secs(1), 5),
+        clock.clone(),
+    ));
+    let mut limiter = Limiter::from_shared(std::io::Cursor::new(vec![]), None, Some(group.clone()));
+    limiter.add_write_bucket(global.clone());
+
+    // The global level is throttled, the group is only charged for what was written
+    assert_eq!(limiter.write(&[4u8; 10]).unwrap(), 10);
+    assert_eq!(clock.elapsed(), Duration::from_secs(2));
+    assert_eq!(group.tokens_available(), 10);
+    assert_eq!(global.tokens_available(), 0);
+}
+
+#[test]
+#[should_panic]
+fn shared_bucket_rejects_leaky_bucket() {
+    let mut opts = LimiterOptions::new(1000, Duration::from_secs(1), 1000);
+    opts.set_mode(LimiterMode::LeakyBucket { chunk_size: 100 });
+    SharedBucket::new(opts);
+}
+
+#[test]
+#[should_panic]
+fn child_bucket_rejects_peak_rate() {
+    let parent = Arc::new(SharedBucket::new(LimiterOptions::new(
+        1000,
+        Duration::from_secs(1),
+        1000,
+    )));
+    let mut ceil = LimiterOptions::new(1000, Duration::from_secs(1), 1000);
+    ceil.set_peak_rate(1000, Duration::from_secs(1), 100);
+    SharedBucket::child(
+        &parent,
+        LimiterOptions::new(100, Duration::from_secs(1), 100),
+        ceil,
+    );
+}
+
+#[test]
+#[should_panic]
+fn shared_bucket_rejects_iops() {
+    let mut opts = LimiterOptions::new(1000, Duration::from_secs(1), 1000);
+    opts.set_iops(10, Duration::from_secs(1), 10);
+    SharedBucket::new(opts);
+}
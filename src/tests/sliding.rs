@@ -0,0 +1,92 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::utils::{recording_limiter, Recorder};
+use crate::{Clock, Limiter, LimiterMode, LimiterOptions, ManualClock};
+
+fn sliding_limiter(clock: &Arc<ManualClock>, bucket_size: u64) -> Limiter<Recorder> {
+    // At most 1000 bytes over any second
+    let mut opts = LimiterOptions::new(1000, Duration::from_secs(1), bucket_size);
+    opts.set_mode(LimiterMode::SlidingWindow);
+    recording_limiter(clock, opts)
+}
+
+/// Get the most bytes written over an interval of `window`
+fn max_in_window(writes: &[(Duration, usize)], window: Duration) -> usize {
+    writes
+        .iter()
+        .map(|(start, _)| {
+            writes
+                .iter()
+                .filter(|(t, _)| t >= start && *t < *start + window)
+                .map(|(_, len)| len)
+                .sum()
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[test]
+fn whole_window_at_once() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = sliding_limiter(&clock, 500);
+    // Nothing was transferred in the last window, it can be used right away
+    assert_eq!(limiter.write(&[0u8; 3000]).unwrap(), 3000);
+    let expected: Vec<(Duration, usize)> = (0..3)
+        .flat_map(|i| [(Duration::from_secs(i), 500), (Duration::from_secs(i), 500)])
+        .collect();
+    assert_eq!(limiter.stream.writes, expected);
+}
+
+#[test]
+fn never_above_window_length() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = sliding_limiter(&clock, 1000);
+    for _ in 0..50 {
+        assert_eq!(limiter.write(&[0u8; 150]).unwrap(), 150);
+        clock.sleep(Duration::from_millis(70));
+    }
+    assert!(max_in_window(&limiter.stream.writes, Duration::from_secs(1)) <= 1000);
+    // The 7th write waits for the 1st one to leave the window
+    assert_eq!(limiter.stream.writes[6], (Duration::from_secs(1), 150));
+}
+
+#[test]
+fn idle_gives_a_single_window() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = sliding_limiter(&clock, 10_000);
+    clock.sleep(Duration::from_secs(10));
+    // Unlike a token bucket, being idle doesn't gather more than a window
+    assert_eq!(limiter.write(&[0u8; 2500]).unwrap(), 2500);
+    assert_eq!(
+        limiter.stream.writes,
+        vec![
+            (Duration::from_secs(10), 1000),
+            (Duration::from_secs(11), 1000),
+            (Duration::from_secs(12), 500),
+        ]
+    );
+}
+
+#[test]
+fn min_operation_size_capped_by_window() {
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(100, Duration::from_secs(1), 1000);
+    opts.set_min_operation_size(500);
+    opts.set_mode(LimiterMode::SlidingWindow);
+    // The window never holds 500 bytes, waiting for them would last forever
+    assert_eq!(opts.sleep_threshold, 100);
+    let mut limiter = recording_limiter(&clock, opts);
+    assert_eq!(limiter.write(&[0u8; 300]).unwrap(), 300);
+    let expected: Vec<(Duration, usize)> = (0..3).map(|i| (Duration::from_secs(i), 100)).collect();
+    assert_eq!(limiter.stream.writes, expected);
+
+    limiter.set_nonblocking(true);
+    let err = limiter.write(&[0u8; 100]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+    assert_eq!(
+        limiter.next_ready_at(),
+        Some(clock.now() + Duration::from_secs(1))
+    );
+}
@@ -0,0 +1,105 @@
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{Limiter, LimiterOptions, LimiterStats, ManualClock};
+
+/// Stream taking 100ms of virtual time for each write
+struct SlowStream(Arc<ManualClock>);
+
+impl Read for SlowStream {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for SlowStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.advance(Duration::from_millis(100));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn throttled_write() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = Limiter::with_clock(
+        SlowStream(clock.clone()),
+        None,
+        Some(LimiterOptions::new(1, Duration::from_secs(1), 1)),
+        clock.clone(),
+    );
+    assert_eq!(limiter.write(&[1u8; 5]).unwrap(), 5);
+    assert_eq!(limiter.write(&[]).unwrap(), 0);
+    assert_eq!(
+        limiter.stats(),
+        (
+            LimiterStats::default(),
+            LimiterStats {
+                bytes: 5,
+                io_time: Duration::from_millis(500),
+                sleep_time: Duration::from_secs(5),
+                throttled: 1,
+                ops_throttled: 0,
+                calls: 2,
+                timeouts: 0,
+            }
+        )
+    );
+}
+
+#[test]
+fn unlimited_read() {
+    let clock = Arc::new(ManualClock::new());
+    let mut limiter = Limiter::with_clock(std::io::Cursor::new(vec![1u8; 10]), None, None, clock);
+    let mut buf = [0u8; 4];
+    assert_eq!(limiter.read(&mut buf).unwrap(), 4);
+    assert_eq!(limiter.read(&mut buf).unwrap(), 4);
+    let stats = limiter.stats().0;
+    assert_eq!(stats.bytes, 8);
+    assert_eq!(stats.calls, 2);
+    assert_eq!(stats.throttled, 0);
+    assert_eq!(stats.sleep_time, Duration::ZERO);
+}
+
+#[test]
+fn timeouts() {
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(1, Duration::from_secs(1), 10);
+    opts.set_timeout(Duration::from_millis(2500));
+    let mut limiter = Limiter::with_clock(
+        std::io::Cursor::new(vec![]),
+        None,
+        Some(opts),
+        clock.clone(),
+    );
+    let err = limiter.write(&[1u8; 10]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    let stats = limiter.stats().1;
+    assert_eq!(stats.timeouts, 1);
+    assert_eq!(stats.throttled, 1);
+    assert_eq!(stats.bytes, 2);
+    assert_eq!(stats.sleep_time, Duration::from_millis(2500));
+}
+
+#[test]
+fn read_from_handle_while_blocked() {
+    let mut limiter = Limiter::new(
+        std::io::Cursor::new(vec![]),
+        None,
+        Some(LimiterOptions::new(10, Duration::from_secs(1), 1)),
+    );
+    let handle = limiter.handle();
+    let writer = std::thread::spawn(move || limiter.write(&[1u8; 10]).unwrap());
+    std::thread::sleep(Duration::from_millis(550));
+    let stats = handle.stats().1;
+    assert_eq!(stats.calls, 1);
+    assert_eq!(stats.throttled, 1);
+    assert!(stats.bytes > 0 && stats.bytes < 10);
+    assert_eq!(writer.join().unwrap(), 10);
+    assert_eq!(handle.stats().1.bytes, 10);
+}
@@ -0,0 +1,127 @@
+use std::time::{Duration, Instant};
+
+use crate::{Clock, Constraint, LimiterMode, LimiterOptions, ManualClock, TokenBucket};
+
+#[test]
+fn try_acquire() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(LimiterOptions::new(10, Duration::from_secs(1), 5), start);
+    // The bucket starts empty
+    assert_eq!(bucket.available(start), 0);
+    assert!(!bucket.try_acquire(1, start));
+    assert_eq!(bucket.time_until(3, start), Duration::from_millis(300));
+
+    let now = start + Duration::from_millis(300);
+    assert!(bucket.try_acquire(3, now));
+    assert!(!bucket.try_acquire(1, now));
+
+    // Capped by the bucket size after a long time
+    let now = start + Duration::from_secs(10);
+    assert_eq!(bucket.available(now), 5);
+    assert!(!bucket.try_acquire(6, now));
+    assert!(bucket.try_acquire(5, now));
+}
+
+#[test]
+fn acquire_sleeps() {
+    let clock = ManualClock::new();
+    let mut bucket = TokenBucket::new(
+        LimiterOptions::new(10, Duration::from_secs(1), 5),
+        clock.now(),
+    );
+    for _ in 0..4 {
+        bucket.acquire(5, &clock);
+    }
+    assert_eq!(clock.elapsed(), Duration::from_secs(2));
+}
+
+#[test]
+#[should_panic]
+fn acquire_above_capacity() {
+    let clock = ManualClock::new();
+    let mut opts = LimiterOptions::new(10, Duration::from_secs(1), 5);
+    opts.set_peak_rate(100, Duration::from_secs(1), 2);
+    let mut bucket = TokenBucket::new(opts, clock.now());
+    assert_eq!(bucket.capacity(), 2);
+    bucket.acquire(3, &clock);
+}
+
+#[test]
+fn time_until_above_capacity() {
+    let start = Instant::now();
+    let bucket = TokenBucket::new(LimiterOptions::new(10, Duration::from_secs(1), 5), start);
+    assert_eq!(bucket.time_until(5, start), Duration::from_millis(500));
+    // Never available
+    assert_eq!(bucket.time_until(6, start), Duration::MAX);
+    assert_eq!(bucket.time_until(u64::MAX, start), Duration::MAX);
+
+    // A capacity above u32::MAX doesn't overflow the computation
+    let bucket = TokenBucket::new(
+        LimiterOptions::new(1, Duration::from_secs(1), u64::MAX),
+        start,
+    );
+    assert_eq!(
+        bucket.time_until(1 << 33, start),
+        Duration::from_secs(1 << 33)
+    );
+}
+
+#[test]
+fn refund() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(LimiterOptions::new(10, Duration::from_secs(1), 5), start);
+    let now = start + Duration::from_secs(1);
+    assert!(bucket.try_acquire(5, now));
+    bucket.refund(2, now);
+    assert_eq!(bucket.available(now), 2);
+    // Never above the bucket size
+    bucket.refund(10, now);
+    assert_eq!(bucket.available(now), 5);
+}
+
+#[test]
+fn refund_modes() {
+    let start = Instant::now();
+    for mode in [LimiterMode::Gcra, LimiterMode::SlidingWindow] {
+        let mut opts = LimiterOptions::new(10, Duration::from_secs(1), 10);
+        opts.set_mode(mode);
+        let mut bucket = TokenBucket::new(opts, start);
+        let now = start + Duration::from_secs(1);
+        assert!(bucket.try_acquire(10, now));
+        assert_eq!(bucket.available(now), 0, "{mode:?}");
+        bucket.refund(4, now);
+        assert_eq!(bucket.available(now), 4, "{mode:?}");
+        bucket.refund(10, now);
+        assert_eq!(bucket.available(now), 10, "{mode:?}");
+    }
+}
+
+#[test]
+fn set_options_keeps_tokens() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(LimiterOptions::new(10, Duration::from_secs(1), 10), start);
+    let now = start + Duration::from_millis(800);
+    bucket.set_options(LimiterOptions::new(1, Duration::from_secs(1), 5), now);
+    assert_eq!(bucket.options().window_length, 1);
+    assert_eq!(bucket.available(now), 5);
+    assert_eq!(bucket.time_until(6, now), Duration::MAX);
+    assert!(bucket.try_acquire(5, now));
+    assert_eq!(bucket.time_until(1, now), Duration::from_secs(1));
+}
+
+#[test]
+fn iops_limit() {
+    let start = Instant::now();
+    let mut opts = LimiterOptions::new(1000, Duration::from_secs(1), 1000);
+    opts.set_iops(2, Duration::from_secs(1), 2);
+    let mut bucket = TokenBucket::new(opts, start);
+    let now = start + Duration::from_secs(1);
+    assert!(bucket.try_acquire(1, now));
+    assert!(bucket.try_acquire(1, now));
+    // Plenty of tokens, but no operation left
+    assert_eq!(bucket.available(now), 0);
+    assert!(!bucket.try_acquire(1, now));
+    assert_eq!(bucket.constraint(1, now), Constraint::Operations);
+    assert_eq!(bucket.time_until(1, now), Duration::from_millis(500));
+    assert_eq!(bucket.constraint(2000, now), Constraint::Bytes);
+}
@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::Instant;
+
+use super::utils::assert_checksum_samedata;
+use crate::{LimiterMode, LimiterOptions, TokioLimiter};
+
+#[tokio::test(start_paused = true)]
+async fn write_one_byte_each_second() {
+    let start = Instant::now();
+    let mut limiter = TokioLimiter::new(
+        vec![],
+        None,
+        Some(LimiterOptions::new(1, Duration::from_secs(1), 1)),
+    );
+    limiter.write_all(&[42u8; 10]).await.unwrap();
+    assert_eq!(start.elapsed(), Duration::from_secs(10));
+    assert_checksum_samedata::<10>(&limiter.stream, 42);
+}
+
+#[tokio::test(start_paused = true)]
+async fn read_exact() {
+    let start = Instant::now();
+    let data = [7u8; 20];
+    let mut limiter = TokioLimiter::new(
+        &data[..],
+        Some(LimiterOptions::new(10, Duration::from_secs(1), 10)),
+        None,
+    );
+    let mut buf = [0u8; 20];
+    limiter.read_exact(&mut buf).await.unwrap();
+    assert_eq!(start.elapsed(), Duration::from_secs(2));
+    assert_checksum_samedata::<20>(&buf, 7);
+}
+
+#[tokio::test(start_paused = true)]
+async fn min_operation_size() {
+    let mut opts = LimiterOptions::new(1, Duration::from_millis(100), 10);
+    opts.set_min_operation_size(5);
+    let mut limiter = TokioLimiter::new(vec![], None, Some(opts));
+    let start = Instant::now();
+    assert_eq!(limiter.write(&[1u8; 20]).await.unwrap(), 5);
+    assert_eq!(start.elapsed(), Duration::from_millis(500));
+    // Smaller than the minimal operation size, only wait for what we write
+    assert_eq!(limiter.write(&[1u8; 3]).await.unwrap(), 3);
+    assert_eq!(start.elapsed(), Duration::from_millis(800));
+}
+
+#[tokio::test(start_paused = true)]
+async fn peak_rate() {
+    let mut opts = LimiterOptions::new(100, Duration::from_secs(1), 1000);
+    opts.set_peak_rate(1000, Duration::from_secs(1), 100);
+    let mut limiter = TokioLimiter::new(vec![], None, Some(opts));
+    // Fill the bucket, it's then drained by chunks of 100 bytes every 100ms
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    let start = Instant::now();
+    assert_eq!(limiter.write(&[1u8; 1000]).await.unwrap(), 100);
+    limiter.write_all(&[1u8; 900]).await.unwrap();
+    assert_eq!(start.elapsed(), Duration::from_millis(900));
+}
+
+#[tokio::test(start_paused = true)]
+async fn gcra() {
+    // The timers of tokio are rounded up to the millisecond, the bucket absorbs it
+    let mut opts = LimiterOptions::new(7, Duration::from_secs(1), 7);
+    opts.set_mode(LimiterMode::Gcra);
+    opts.set_min_operation_size(1);
+    let mut limiter = TokioLimiter::new(vec![], None, Some(opts));
+    let start = Instant::now();
+    for _ in 0..70 {
+        limiter.write_all(&[1u8]).await.unwrap();
+    }
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed >= Duration::from_secs(10) && elapsed <= Duration::from_millis(10_001),
+        "{elapsed:?}"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn timeout() {
+    let mut opts = LimiterOptions::new(1, Duration::from_secs(1), 10);
+    opts.set_min_operation_size(5);
+    opts.set_timeout(Duration::from_millis(2500));
+    let mut limiter = TokioLimiter::new(vec![], None, Some(opts));
+    let start = Instant::now();
+    let err = limiter.write(&[1u8; 10]).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert_eq!(start.elapsed(), Duration::from_millis(2500));
+    assert!(limiter.stream.is_empty());
+
+    // The tokens gathered before the timeout are kept for the next write
+    assert_eq!(limiter.write(&[1u8; 4]).await.unwrap(), 4);
+    assert_eq!(start.elapsed(), Duration::from_millis(4500));
+}
+
+#[tokio::test(start_paused = true)]
+async fn cancelled_write_doesnt_time_out_the_next() {
+    let mut opts = LimiterOptions::new(1, Duration::from_secs(1), 10);
+    opts.set_min_operation_size(5);
+    opts.set_timeout(Duration::from_millis(2500));
+    let mut limiter = TokioLimiter::new(vec![], None, Some(opts));
+    let start = Instant::now();
+    // The pending write is dropped before its own timeout
+    assert!(
+        tokio::time::timeout(Duration::from_secs(1), limiter.write(&[1u8; 10]))
+            .await
+            .is_err()
+    );
+    tokio::time::sleep(Duration::from_secs(4)).await;
+
+    // 5 tokens are available, the start of the dropped write is forgotten
+    assert_eq!(limiter.write(&[1u8; 5]).await.unwrap(), 5);
+    assert_eq!(start.elapsed(), Duration::from_secs(5));
+}
+
+#[tokio::test(start_paused = true)]
+async fn unlimited() {
+    let start = Instant::now();
+    let mut limiter = TokioLimiter::new(vec![], None, None);
+    limiter.write_all(&[3u8; 1000]).await.unwrap();
+    assert_eq!(start.elapsed(), Duration::ZERO);
+    assert_eq!(limiter.limits(), (false, false));
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use crate::{Limiter, LimiterOptions, ManualClock};
+
+/// Fields of a span or an event, formatted with Debug
+#[derive(Default)]
+struct Fields(HashMap<String, String>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+}
+
+/// Fields of the span of an event, and of the event itself
+type Recorded = (HashMap<String, String>, HashMap<String, String>);
+
+/// Records the events with the fields of the span they happened in
+#[derive(Default)]
+struct Collector {
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, Fields>>,
+    current: Mutex<Vec<u64>>,
+    events: Arc<Mutex<Vec<Recorded>>>,
+}
+
+impl Subscriber for Collector {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut fields = Fields::default();
+        span.record(&mut fields);
+        self.spans.lock().unwrap().insert(id, fields);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let span = match self.current.lock().unwrap().last() {
+            Some(id) => self.spans.lock().unwrap()[id].0.clone(),
+            None => HashMap::new(),
+        };
+        self.events.lock().unwrap().push((span, fields.0));
+    }
+
+    fn enter(&self, span: &Id) {
+        self.current.lock().unwrap().push(span.into_u64());
+    }
+
+    fn exit(&self, _span: &Id) {
+        self.current.lock().unwrap().pop();
+    }
+}
+
+#[test]
+fn read_events() {
+    let collector = Collector::default();
+    let events = collector.events.clone();
+    let clock = Arc::new(ManualClock::new());
+    let mut opts = LimiterOptions::new(1, Duration::from_secs(1), 1);
+    opts.set_timeout(Duration::from_secs(3));
+    let mut limiter =
+        Limiter::with_clock(std::io::Cursor::new(vec![0u8; 10]), Some(opts), None, clock);
+    limiter.set_name("peer-1");
+    assert_eq!(limiter.name(), Some("peer-1"));
+
+    let res = tracing::subscriber::with_default(collector, || limiter.read(&mut [0u8; 5]));
+    assert_eq!(res.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+
+    let events = events.lock().unwrap();
+    for (span, _) in events.iter() {
+        assert_eq!(span["direction"], "\"read\"");
+        assert_eq!(span["name"], "\"peer-1\"");
+    }
+    let messages: Vec<&str> = events
+        .iter()
+        .map(|(_, fields)| fields["message"].as_str())
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "Tokens available",
+            "Sleeping for tokens",
+            "Tokens available",
+            "Chunk transferred",
+            "Tokens available",
+            "Sleeping for tokens",
+            "Tokens available",
+            "Chunk transferred",
+            "Tokens available",
+            "Sleeping for tokens",
+            "Timeout",
+        ]
+    );
+    assert_eq!(events[1].1["tsleep"], "1s");
+    assert_eq!(events[3].1["bytes"], "1");
+    assert_eq!(events[10].1["elapsed"], "3s");
+}
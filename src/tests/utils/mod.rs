@@ -1,9 +1,13 @@
-#![allow(dead_code)]
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
 use std::{fs::File, path::PathBuf};
 
 use hex_literal::hex;
 use sha2::Digest;
 
+use crate::{Limiter, LimiterOptions, ManualClock};
+
 pub mod paramtests;
 
 // The checksum and the size of the data (to trim the buffer)
@@ -46,3 +50,39 @@ pub fn open_file(fname: &'static str) -> File {
     assert!(fpath.exists());
     File::open(fpath).unwrap()
 }
+
+/// Stream recording the virtual time and size of each write
+pub struct Recorder {
+    pub clock: Arc<ManualClock>,
+    pub writes: Vec<(Duration, usize)>,
+}
+
+impl Read for Recorder {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for Recorder {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writes.push((self.clock.elapsed(), buf.len()));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Limiter recording the writes limited by `write_opt` on the virtual clock
+pub fn recording_limiter(clock: &Arc<ManualClock>, write_opt: LimiterOptions) -> Limiter<Recorder> {
+    Limiter::with_clock(
+        Recorder {
+            clock: clock.clone(),
+            writes: Vec::new(),
+        },
+        None,
+        Some(write_opt),
+        clock.clone(),
+    )
+}
@@ -0,0 +1,156 @@
+//! Asynchronous limiter for the tokio runtime, enabled with the `tokio` feature.
+//! It uses the same `LimiterOptions` as the `Limiter`, but waits for its tokens with
+//! a `tokio::time::Sleep` instead of blocking the thread.
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::async_bucket::{AsyncBucket, Timer};
+use crate::LimiterOptions;
+
+/// Timer of the tokio runtime, follows its clock (so the time can be paused in tests)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioTimer;
+
+impl Timer for TokioTimer {
+    type Sleep = tokio::time::Sleep;
+
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn sleep(&self, dur: Duration) -> Self::Sleep {
+        tokio::time::sleep(dur)
+    }
+}
+
+/// Asynchronous version of the `Limiter`, for streams implementing tokio's
+/// `AsyncRead` and `AsyncWrite`.
+/// Unlike `Limiter::read`, a read returns as soon as some bytes were read, as any
+/// `AsyncRead` (use `AsyncReadExt::read_exact` to fill the whole buffer).
+pub struct TokioLimiter<S> {
+    pub stream: S,
+    read: AsyncBucket<TokioTimer>,
+    write: AsyncBucket<TokioTimer>,
+}
+
+impl<S> TokioLimiter<S> {
+    /// Create a new `TokioLimiter` with the given options passed in parameter
+    /// If an option is None, the operation will be performed on the raw stream
+    pub fn new(
+        stream: S,
+        read_opt: Option<LimiterOptions>,
+        write_opt: Option<LimiterOptions>,
+    ) -> TokioLimiter<S> {
+        TokioLimiter {
+            stream,
+            read: AsyncBucket::new(&TokioTimer, read_opt),
+            write: AsyncBucket::new(&TokioTimer, write_opt),
+        }
+    }
+
+    /// Change the options limiting the read operations, without resetting the bucket.
+    /// The tokens gathered with the previous options are kept (up to the new bucket size)
+    pub fn set_read_options(&mut self, read_opt: Option<LimiterOptions>) {
+        self.read.set_options(&TokioTimer, read_opt);
+    }
+
+    /// Change the options limiting the write operations, without resetting the bucket.
+    /// The tokens gathered with the previous options are kept (up to the new bucket size)
+    pub fn set_write_options(&mut self, write_opt: Option<LimiterOptions>) {
+        self.write.set_options(&TokioTimer, write_opt);
+    }
+
+    /// Get if this TokioLimiter limits the read or write stream (or none)
+    pub fn limits(&self) -> (bool, bool) {
+        (self.read.is_limited(), self.write.is_limited())
+    }
+
+    /// Get the raw stream, deconstruct the TokioLimiter struct.
+    pub fn get_stream(self) -> S {
+        self.stream
+    }
+}
+
+impl<S> AsyncRead for TokioLimiter<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let len = u64::try_from(buf.remaining()).expect("R buflen to u64");
+        // If the stream isn't limited, read instantly instead
+        if !this.read.is_limited() || len == 0 {
+            return Pin::new(&mut this.stream).poll_read(cx, buf);
+        }
+
+        let nb_bytes_readable =
+            ready!(this.read.poll_tokens(&TokioTimer, cx, len, "Read timeout"))?;
+        let limit = usize::try_from(nb_bytes_readable).expect("R limit to usize");
+        let mut limited = ReadBuf::new(buf.initialize_unfilled_to(limit));
+        match Pin::new(&mut this.stream).poll_read(cx, &mut limited) {
+            Poll::Ready(Ok(())) => {
+                let read_now = limited.filled().len();
+                buf.advance(read_now);
+                this.read.consume(
+                    &TokioTimer,
+                    u64::try_from(read_now).expect("R read_now to u64"),
+                );
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S> AsyncWrite for TokioLimiter<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let len = u64::try_from(buf.len()).expect("W buflen to u64");
+        // If the stream isn't limited, write instantly instead
+        if !this.write.is_limited() || len == 0 {
+            return Pin::new(&mut this.stream).poll_write(cx, buf);
+        }
+
+        let nb_bytes_writable =
+            ready!(this
+                .write
+                .poll_tokens(&TokioTimer, cx, len, "Write timeout"))?;
+        let limit = usize::try_from(nb_bytes_writable).expect("W limit to usize");
+        match Pin::new(&mut this.stream).poll_write(cx, &buf[..limit]) {
+            Poll::Ready(Ok(write_now)) => {
+                this.write.consume(
+                    &TokioTimer,
+                    u64::try_from(write_now).expect("W write_now to u64"),
+                );
+                Poll::Ready(Ok(write_now))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Flush the underlying stream
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
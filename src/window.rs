@@ -0,0 +1,106 @@
+//! Log of the operations of the last window, used by the sliding window mode to
+//! guarantee that no interval of `window_time` ever transfers more than `window_length`.
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Number of entries kept in the log over a window, the operations are merged into them
+const LOG_SLOTS: u32 = 64;
+
+/// Operations merged in the same slot of the log
+#[derive(Clone, Debug)]
+struct Entry {
+    /// Instant of the first operation, the slot lasts `window_time / LOG_SLOTS` from it
+    start: Instant,
+    /// Instant of the last operation, the entry leaves the window from it
+    last: Instant,
+    bytes: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct WindowLog {
+    entries: VecDeque<Entry>,
+}
+
+impl WindowLog {
+    /// Get the number of bytes transferred in the window ending at `now`
+    fn used(&self, window_time: Duration, now: Instant) -> u64 {
+        self.entries
+            .iter()
+            .filter(|entry| entry.last + window_time > now)
+            .map(|entry| entry.bytes)
+            .sum()
+    }
+
+    /// Get the number of bytes that can be transferred at `now`
+    pub(crate) fn available(&self, window_length: u64, window_time: Duration, now: Instant) -> u64 {
+        window_length.saturating_sub(self.used(window_time, now))
+    }
+
+    /// Add an operation of `nb` bytes done at `now`, and forget the ones out of the window.
+    /// Merging an operation into a slot delays the whole slot, so it never lets more
+    /// bytes in the window than the ones actually transferred.
+    pub(crate) fn record(&mut self, nb: u64, window_time: Duration, now: Instant) {
+        while self
+            .entries
+            .front()
+            .is_some_and(|entry| entry.last + window_time <= now)
+        {
+            self.entries.pop_front();
+        }
+        if nb == 0 {
+            return;
+        }
+        match self.entries.back_mut() {
+            Some(entry) if now < entry.start + window_time / LOG_SLOTS => {
+                entry.last = entry.last.max(now);
+                entry.bytes = entry.bytes.saturating_add(nb);
+            }
+            _ => self.entries.push_back(Entry {
+                start: now,
+                last: now,
+                bytes: nb,
+            }),
+        }
+    }
+
+    /// Forget `nb` bytes of the latest operations, given back as they weren't used
+    pub(crate) fn refund(&mut self, mut nb: u64) {
+        while nb > 0 {
+            let Some(entry) = self.entries.back_mut() else {
+                return;
+            };
+            if entry.bytes > nb {
+                entry.bytes -= nb;
+                return;
+            }
+            nb -= entry.bytes;
+            self.entries.pop_back();
+        }
+    }
+
+    /// Get the time to wait from `now` until `nb` bytes can be transferred
+    pub(crate) fn time_until(
+        &self,
+        nb: u64,
+        window_length: u64,
+        window_time: Duration,
+        now: Instant,
+    ) -> Duration {
+        let mut used = self.used(window_time, now);
+        if window_length.saturating_sub(used) >= nb {
+            return Duration::ZERO;
+        }
+        // Wait for the oldest operations to leave the window
+        for entry in self
+            .entries
+            .iter()
+            .filter(|entry| entry.last + window_time > now)
+        {
+            used = used.saturating_sub(entry.bytes);
+            if window_length.saturating_sub(used) >= nb {
+                return (entry.last + window_time).saturating_duration_since(now);
+            }
+        }
+        window_time
+    }
+}